@@ -0,0 +1,91 @@
+//! Run with `cargo bench -p rust-learning --bench arena_bench`, or add a filter such as
+//! `-- strings` to run one group.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use rust_learning::allocator::Arena;
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: f64,
+    y: f64,
+    id: u64,
+}
+
+const SIZES: [usize; 3] = [16, 256, 4096];
+
+// One request's worth of small structs, each allocated separately and freed together.
+fn small_values(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_values");
+    for size in SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("global", size), &size, |b, &size| {
+            b.iter(|| {
+                let points = (0..size)
+                    .map(|i| {
+                        Box::new(Point {
+                            x: i as f64,
+                            y: 0.5,
+                            id: i as u64,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                black_box(points.iter().map(|p| p.x + p.y + p.id as f64).sum::<f64>())
+            })
+        });
+
+        // reset between iterations like a server would between requests, so the chunks are
+        // warm after the first one
+        let mut arena = Arena::new();
+        group.bench_with_input(BenchmarkId::new("arena", size), &size, |b, &size| {
+            b.iter(|| {
+                arena.reset();
+                let points = (0..size)
+                    .map(|i| {
+                        &*arena.alloc(Point {
+                            x: i as f64,
+                            y: 0.5,
+                            id: i as u64,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                black_box(points.iter().map(|p| p.x + p.y + p.id as f64).sum::<f64>())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn strings(c: &mut Criterion) {
+    let words = "kenshi yonezu lemon uchiage hanabi peace sign"
+        .split(' ')
+        .cycle()
+        .take(1024)
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("strings");
+    group.throughput(Throughput::Elements(words.len() as u64));
+
+    group.bench_function("global", |b| {
+        b.iter(|| {
+            let owned = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+            black_box(owned.iter().map(String::len).sum::<usize>())
+        })
+    });
+
+    let mut arena = Arena::with_capacity(16 * 1024);
+    group.bench_function("arena", |b| {
+        b.iter(|| {
+            arena.reset();
+            let owned = words
+                .iter()
+                .map(|w| &*arena.alloc_str(w))
+                .collect::<Vec<_>>();
+            black_box(owned.iter().map(|w| w.len()).sum::<usize>())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, small_values, strings);
+criterion_main!(benches);
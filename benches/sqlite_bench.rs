@@ -1,5 +1,14 @@
-use criterion::{Criterion, black_box, criterion_group, criterion_main};
-use sqlx::{SqlitePool, query};
+//! Run with `cargo bench -p rust-learning --bench sqlite_bench`, or add a filter such as
+//! `-- journal_mode` to run one group.
+
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use sqlx::{
+    QueryBuilder, Sqlite, SqlitePool, query,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
+use tempfile::TempDir;
 use tokio::runtime::Runtime;
 
 async fn run_query(pool: &SqlitePool) {
@@ -48,5 +57,211 @@ fn sqlite_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, sqlite_benchmark);
+/// A migrated database file in its own temp dir, deleted on drop.
+struct FileDb {
+    pool: SqlitePool,
+    _dir: TempDir,
+}
+
+async fn file_db(
+    journal_mode: SqliteJournalMode,
+    synchronous: SqliteSynchronous,
+    connections: u32,
+) -> FileDb {
+    let dir = tempfile::tempdir().unwrap();
+    let options = SqliteConnectOptions::new()
+        .filename(dir.path().join("songs.db"))
+        .create_if_missing(true)
+        .journal_mode(journal_mode)
+        .synchronous(synchronous);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(connections)
+        .min_connections(connections)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    FileDb { pool, _dir: dir }
+}
+
+async fn insert_single(pool: &SqlitePool, rows: usize) {
+    for i in 0..rows {
+        query("INSERT INTO songs (artist, title) VALUES (?, ?)")
+            .bind("artist")
+            .bind(format!("song {i}"))
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+}
+
+async fn insert_transaction(pool: &SqlitePool, rows: usize) {
+    let mut tx = pool.begin().await.unwrap();
+    for i in 0..rows {
+        query("INSERT INTO songs (artist, title) VALUES (?, ?)")
+            .bind("artist")
+            .bind(format!("song {i}"))
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+    }
+    tx.commit().await.unwrap();
+}
+
+async fn insert_multi_row(pool: &SqlitePool, rows: usize) {
+    let mut builder = QueryBuilder::<Sqlite>::new("INSERT INTO songs (artist, title) ");
+    builder.push_values(0..rows, |mut row, i| {
+        row.push_bind("artist").push_bind(format!("song {i}"));
+    });
+    builder.build().execute(pool).await.unwrap();
+}
+
+// Every autocommitted insert is its own fsync, so this is where batching pays off.
+fn batching(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let db = rt.block_on(file_db(
+        SqliteJournalMode::Wal,
+        SqliteSynchronous::Normal,
+        1,
+    ));
+    let mut group = c.benchmark_group("batching");
+    group.sample_size(20);
+
+    for rows in [1, 10, 100, 1000] {
+        group.throughput(Throughput::Elements(rows as u64));
+        group.bench_with_input(BenchmarkId::new("single", rows), &rows, |b, &rows| {
+            b.to_async(&rt).iter(|| insert_single(&db.pool, rows))
+        });
+        group.bench_with_input(BenchmarkId::new("transaction", rows), &rows, |b, &rows| {
+            b.to_async(&rt).iter(|| insert_transaction(&db.pool, rows))
+        });
+        group.bench_with_input(BenchmarkId::new("multi_row", rows), &rows, |b, &rows| {
+            b.to_async(&rt).iter(|| insert_multi_row(&db.pool, rows))
+        });
+    }
+    group.finish();
+}
+
+// 64 concurrent lookups, or 56 lookups and 8 inserts, against pools of different sizes.
+async fn concurrent(pool: &SqlitePool, writers: usize) {
+    let tasks = (0..64).map(|i| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if i < writers {
+                insert_single(&pool, 1).await;
+            } else {
+                let _: (String,) = sqlx::query_as("SELECT title FROM songs WHERE id = ?")
+                    .bind(i as i64 + 1)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            }
+        })
+    });
+    for task in futures::future::join_all(tasks).await {
+        task.unwrap();
+    }
+}
+
+fn pool_size(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("pool_size");
+    group.throughput(Throughput::Elements(64));
+
+    for connections in [1, 2, 4, 8, 16] {
+        let db = rt.block_on(async {
+            let db = file_db(
+                SqliteJournalMode::Wal,
+                SqliteSynchronous::Normal,
+                connections,
+            )
+            .await;
+            insert_transaction(&db.pool, 1000).await;
+            db
+        });
+        group.bench_with_input(BenchmarkId::new("reads", connections), &db, |b, db| {
+            b.to_async(&rt).iter(|| concurrent(&db.pool, 0))
+        });
+        group.bench_with_input(BenchmarkId::new("mixed", connections), &db, |b, db| {
+            b.to_async(&rt).iter(|| concurrent(&db.pool, 8))
+        });
+    }
+    group.finish();
+}
+
+async fn insert_and_lookup(pool: &SqlitePool) {
+    insert_transaction(pool, 10).await;
+    let _: (String,) = sqlx::query_as("SELECT title FROM songs WHERE id = ?")
+        .bind(1_i64)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+}
+
+fn journal_mode(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("journal_mode");
+    group
+        .sample_size(20)
+        .measurement_time(Duration::from_secs(10));
+
+    for (name, journal_mode, synchronous) in [
+        ("delete", SqliteJournalMode::Delete, SqliteSynchronous::Full),
+        ("wal", SqliteJournalMode::Wal, SqliteSynchronous::Full),
+        (
+            "wal_normal",
+            SqliteJournalMode::Wal,
+            SqliteSynchronous::Normal,
+        ),
+    ] {
+        let db = rt.block_on(file_db(journal_mode, synchronous, 1));
+        group.bench_function(BenchmarkId::new("autocommit_insert", name), |b| {
+            b.to_async(&rt).iter(|| insert_single(&db.pool, 1))
+        });
+        group.bench_function(BenchmarkId::new("insert_and_lookup", name), |b| {
+            b.to_async(&rt).iter(|| insert_and_lookup(&db.pool))
+        });
+    }
+    group.finish();
+}
+
+// sqlx keeps prepared statements per connection unless a query opts out with
+// `persistent(false)`, which has sqlite parse and plan it every time.
+fn statement_cache(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let db = rt.block_on(async {
+        let db = file_db(SqliteJournalMode::Wal, SqliteSynchronous::Normal, 1).await;
+        insert_transaction(&db.pool, 1000).await;
+        db
+    });
+    let sql =
+        "SELECT id, artist, title FROM songs WHERE artist = ? AND id > ? ORDER BY id LIMIT 10";
+    let mut group = c.benchmark_group("statement_cache");
+
+    for persistent in [true, false] {
+        let name = if persistent { "cached" } else { "uncached" };
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| async {
+                let rows: Vec<(i64, String, String)> = sqlx::query_as(sql)
+                    .bind("artist")
+                    .bind(500_i64)
+                    .persistent(persistent)
+                    .fetch_all(&db.pool)
+                    .await
+                    .unwrap();
+                black_box(rows)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    sqlite_benchmark,
+    batching,
+    pool_size,
+    journal_mode,
+    statement_cache
+);
 criterion_main!(benches);
@@ -0,0 +1,489 @@
+//! Derives for `rust_learning`'s validator module, re-exported from there.
+//!
+//! They expand to `::rust_learning::...` paths. Code that reaches the library under another
+//! name, or the library itself, says where it is with `crate = path`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, parse_macro_input};
+
+/// Implements `rust_learning::validator::async_validate::AsyncValidate` for a struct.
+///
+/// ```ignore
+/// #[derive(AsyncValidate)]
+/// #[async_validate(context = SqlitePool)]
+/// struct Signup {
+///     #[async_validate(function = unique_username)]
+///     username: String,
+/// }
+///
+/// async fn unique_username(username: &str, pool: &SqlitePool) -> Result<(), ValidationError>
+/// ```
+///
+/// `#[async_validate(crate = path)]` points the generated code somewhere other than
+/// `::rust_learning`.
+#[proc_macro_derive(AsyncValidate, attributes(async_validate))]
+pub fn derive_async_validate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_async_validate(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_async_validate(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    let mut context: Option<syn::Type> = None;
+    let mut krate = default_crate();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("async_validate"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("context") {
+                context = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `context = Type` or `crate = path`"))
+            }
+        })?;
+    }
+    let async_validate = quote! { #krate::validator::async_validate };
+    let Some(context) = context else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "#[derive(AsyncValidate)] needs #[async_validate(context = Type)]",
+        ));
+    };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "#[derive(AsyncValidate)] can only be used on structs",
+        ));
+    };
+
+    let mut checks = Vec::new();
+    for field in &data.fields {
+        let Some(ident) = &field.ident else {
+            return Err(syn::Error::new_spanned(
+                field,
+                "#[derive(AsyncValidate)] needs named fields",
+            ));
+        };
+        let key = ident.to_string().trim_start_matches("r#").to_owned();
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("async_validate"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("function") {
+                    return Err(meta.error("expected `function = path`"));
+                }
+                // accept both `function = check` and `function = "check"`, like validator does
+                let value = meta.value()?;
+                let function: syn::Path = if value.peek(syn::LitStr) {
+                    value.parse::<syn::LitStr>()?.parse()?
+                } else {
+                    value.parse()?
+                };
+                checks.push(quote! {
+                    (#key, ::std::boxed::Box::pin(#function(&self.#ident, ctx))
+                        as #async_validate::AsyncCheck<'__a>)
+                });
+                Ok(())
+            })?;
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #async_validate::AsyncValidate for #name #ty_generics #where_clause
+        {
+            type Context = #context;
+
+            fn validate_async<'__a>(
+                &'__a self,
+                ctx: &'__a Self::Context,
+            ) -> #async_validate::AsyncValidation<'__a> {
+                ::std::boxed::Box::pin(#async_validate::run_checks(vec![#(#checks),*]))
+            }
+        }
+    })
+}
+
+/// Implements `rust_learning::validator::schema::Schema` for a struct, turning its `#[validate]`
+/// rules into JSON Schema keywords and respecting
+/// `#[serde(rename, rename_all, default, skip, flatten)]`.
+///
+/// `#[schema(crate = path)]` points the generated code somewhere other than `::rust_learning`.
+#[proc_macro_derive(Schema, attributes(schema, validate, serde))]
+pub fn derive_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_schema(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn default_crate() -> syn::Path {
+    syn::parse_quote!(::rust_learning)
+}
+
+// the `crate = path` in `#[schema(...)]`, if any
+fn schema_crate(attrs: &[syn::Attribute]) -> syn::Result<syn::Path> {
+    let mut krate = default_crate();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("schema")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `crate = path`"))
+            }
+        })?;
+    }
+    Ok(krate)
+}
+
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    default: bool,
+    skip: bool,
+    flatten: bool,
+}
+
+fn serde_attrs(attrs: &[syn::Attribute]) -> syn::Result<SerdeAttrs> {
+    let mut out = SerdeAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                out.rename = deserialize_name(&meta)?;
+            } else if meta.path.is_ident("rename_all") {
+                out.rename_all = deserialize_name(&meta)?;
+            } else if meta.path.is_ident("flatten") {
+                out.flatten = true;
+            } else if meta.path.is_ident("default") {
+                out.default = true;
+                skip_meta(&meta)?;
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                out.skip = true;
+            } else {
+                skip_meta(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(out)
+}
+
+// The value of `rename = "..."`, or the `deserialize` half of
+// `rename(serialize = "...", deserialize = "...")`, since that's the name a client sends.
+// Without one the field keeps its default name.
+fn deserialize_name(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(syn::Token![=]) {
+        return Ok(Some(meta.value()?.parse::<syn::LitStr>()?.value()));
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|side| {
+        let value = side.value()?.parse::<syn::LitStr>()?.value();
+        if side.path.is_ident("deserialize") {
+            name = Some(value);
+        }
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+// consumes `= value` or `(...)` for attribute items we don't care about
+fn skip_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let _content;
+        syn::parenthesized!(_content in meta.input);
+    }
+    Ok(())
+}
+
+fn rename_field(name: &str, rule: &str) -> syn::Result<String> {
+    let words = name.split('_').filter(|w| !w.is_empty());
+    let capitalize = |w: &str| {
+        let mut chars = w.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+    Ok(match rule {
+        "lowercase" | "snake_case" => name.to_owned(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => name.to_uppercase(),
+        "kebab-case" => name.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => name.replace('_', "-").to_uppercase(),
+        "PascalCase" => words.map(capitalize).collect(),
+        "camelCase" => words
+            .enumerate()
+            .map(|(i, w)| if i == 0 { w.to_owned() } else { capitalize(w) })
+            .collect(),
+        _ => {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                format!("unsupported rename_all rule {rule:?}"),
+            ));
+        }
+    })
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(p) if p.path.segments.last().is_some_and(|s| s.ident == "Option"))
+}
+
+// `T` in `Option<T>`
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(p) = ty else {
+        return None;
+    };
+    let last = p.path.segments.last().filter(|s| s.ident == "Option")?;
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        syn::GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+fn doc_comment(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(s),
+                        ..
+                    }),
+                ..
+            }) => Some(s.value().trim().to_owned()),
+            _ => None,
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+// turns one field's #[validate(...)] rules into statements that edit `schema`
+fn validate_rules(
+    attrs: &[syn::Attribute],
+    schema_mod: &proc_macro2::TokenStream,
+    required: &mut bool,
+) -> syn::Result<Vec<proc_macro2::TokenStream>> {
+    let mut rules = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("validate")) {
+        attr.parse_nested_meta(|meta| {
+            let rule = meta
+                .path
+                .get_ident()
+                .map(|i| i.to_string())
+                .unwrap_or_default();
+            match rule.as_str() {
+                "email" => {
+                    rules.push(quote! { #schema_mod::set(&mut schema, "format", "email"); });
+                    skip_meta(&meta)?;
+                }
+                "url" => {
+                    rules.push(quote! { #schema_mod::set(&mut schema, "format", "uri"); });
+                    skip_meta(&meta)?;
+                }
+                "required" => {
+                    *required = true;
+                    skip_meta(&meta)?;
+                }
+                "length" | "range" => {
+                    meta.parse_nested_meta(|arg| {
+                        let key = arg
+                            .path
+                            .get_ident()
+                            .map(|i| i.to_string())
+                            .unwrap_or_default();
+                        let value = arg.value()?.parse::<syn::Expr>()?;
+                        let calls = match (rule.as_str(), key.as_str()) {
+                            ("length", "min") => {
+                                vec![quote! { length(&mut schema, "min", #value) }]
+                            }
+                            ("length", "max") => {
+                                vec![quote! { length(&mut schema, "max", #value) }]
+                            }
+                            ("length", "equal") => vec![
+                                quote! { length(&mut schema, "min", #value) },
+                                quote! { length(&mut schema, "max", #value) },
+                            ],
+                            ("range", "min") => {
+                                vec![quote! { set(&mut schema, "minimum", #value) }]
+                            }
+                            ("range", "max") => {
+                                vec![quote! { set(&mut schema, "maximum", #value) }]
+                            }
+                            ("range", "exclusive_min") => {
+                                vec![quote! { set(&mut schema, "exclusiveMinimum", #value) }]
+                            }
+                            ("range", "exclusive_max") => {
+                                vec![quote! { set(&mut schema, "exclusiveMaximum", #value) }]
+                            }
+                            // message, code
+                            _ => Vec::new(),
+                        };
+                        rules.extend(calls.into_iter().map(|call| quote! { #schema_mod::#call; }));
+                        Ok(())
+                    })?;
+                }
+                "custom" => {
+                    meta.parse_nested_meta(|arg| {
+                        if arg.path.is_ident("function") {
+                            let input = arg.value()?;
+                            let name = if input.peek(syn::LitStr) {
+                                input.parse::<syn::LitStr>()?.value()
+                            } else {
+                                let path = input.parse::<syn::Path>()?;
+                                quote!(#path).to_string().replace(' ', "")
+                            };
+                            rules.push(quote! { #schema_mod::custom(&mut schema, #name); });
+                        } else {
+                            skip_meta(&arg)?;
+                        }
+                        Ok(())
+                    })?;
+                }
+                // nested is covered by the field's own schema, the rest has no JSON Schema
+                // equivalent
+                _ => skip_meta(&meta)?,
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rules)
+}
+
+fn expand_schema(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let krate = schema_crate(&input.attrs)?;
+    let schema_mod = quote! { #krate::validator::schema };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "#[derive(Schema)] can only be used on structs",
+        ));
+    };
+    let container = serde_attrs(&input.attrs)?;
+    let title = container.rename.clone().unwrap_or_else(|| name.to_string());
+
+    let mut properties = Vec::new();
+    let mut required_fields = Vec::new();
+    let mut flattened = Vec::new();
+    let mut names = Vec::new();
+    for field in &data.fields {
+        let Some(ident) = &field.ident else {
+            return Err(syn::Error::new_spanned(
+                field,
+                "#[derive(Schema)] needs named fields",
+            ));
+        };
+        let serde = serde_attrs(&field.attrs)?;
+        if serde.skip {
+            continue;
+        }
+        let rust_name = ident.to_string().trim_start_matches("r#").to_owned();
+        let ty = &field.ty;
+        if serde.flatten {
+            names.push(quote! {
+                #rust_name => ::std::option::Option::Some(#schema_mod::FieldName {
+                    name: "",
+                    fields: <#ty as #schema_mod::Schema>::field_name,
+                }),
+            });
+            // its fields sit next to ours, so it's inlined rather than referenced
+            let (ty, optional) = match option_inner(&field.ty) {
+                Some(inner) => (inner, true),
+                None => (&field.ty, serde.default || container.default),
+            };
+            flattened.push(quote! {
+                #schema_mod::flatten(
+                    &mut schema,
+                    <#ty as #schema_mod::Schema>::schema(defs),
+                    #optional,
+                );
+            });
+            continue;
+        }
+
+        let key = match (&serde.rename, &container.rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(rule)) => rename_field(&rust_name, rule)?,
+            (None, None) => rust_name.clone(),
+        };
+        names.push(quote! {
+            #rust_name => ::std::option::Option::Some(#schema_mod::FieldName {
+                name: #key,
+                fields: <#ty as #schema_mod::Schema>::field_name,
+            }),
+        });
+
+        let mut required = !is_option(&field.ty) && !serde.default && !container.default;
+        let rules = validate_rules(&field.attrs, &schema_mod, &mut required)?;
+        if required {
+            required_fields.push(key.clone());
+        }
+
+        let description = doc_comment(&field.attrs).map(|doc| {
+            quote! { #schema_mod::set(&mut schema, "description", #doc); }
+        });
+        properties.push(quote! {
+            let mut schema = <#ty as #schema_mod::Schema>::reference(defs);
+            #description
+            #(#rules)*
+            properties.insert(#key.to_owned(), schema);
+        });
+    }
+
+    let description = doc_comment(&input.attrs).map(|doc| {
+        quote! { #schema_mod::set(&mut schema, "description", #doc); }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #schema_mod::Schema for #name #ty_generics #where_clause {
+            fn name() -> ::std::option::Option<::std::string::String> {
+                ::std::option::Option::Some(#title.to_owned())
+            }
+
+            fn schema(defs: &mut #schema_mod::Definitions) -> #schema_mod::Value {
+                let mut properties = #schema_mod::Map::new();
+                #(#properties)*
+                let required: ::std::vec::Vec<&str> = vec![#(#required_fields),*];
+                let mut schema = #schema_mod::object(#title, properties, &required);
+                #(#flattened)*
+                #description
+                schema
+            }
+
+            fn field_name(
+                field: &str,
+            ) -> ::std::option::Option<#schema_mod::FieldName> {
+                match field {
+                    #(#names)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+    })
+}
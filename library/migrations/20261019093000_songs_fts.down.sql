@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TRIGGER songs_fts_update;
+DROP TRIGGER songs_fts_delete;
+DROP TRIGGER songs_fts_insert;
+DROP TABLE songs_fts;
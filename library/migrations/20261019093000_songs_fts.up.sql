@@ -0,0 +1,26 @@
+-- Add up migration script here
+-- external content table: the text lives in songs, songs_fts only holds the index
+CREATE VIRTUAL TABLE songs_fts USING fts5(
+    artist,
+    title,
+    content = 'songs',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO songs_fts (songs_fts) VALUES ('rebuild');
+
+CREATE TRIGGER songs_fts_insert AFTER INSERT ON songs BEGIN
+    INSERT INTO songs_fts (rowid, artist, title) VALUES (new.id, new.artist, new.title);
+END;
+
+CREATE TRIGGER songs_fts_delete AFTER DELETE ON songs BEGIN
+    INSERT INTO songs_fts (songs_fts, rowid, artist, title)
+    VALUES ('delete', old.id, old.artist, old.title);
+END;
+
+CREATE TRIGGER songs_fts_update AFTER UPDATE ON songs BEGIN
+    INSERT INTO songs_fts (songs_fts, rowid, artist, title)
+    VALUES ('delete', old.id, old.artist, old.title);
+    INSERT INTO songs_fts (rowid, artist, title) VALUES (new.id, new.artist, new.title);
+END;
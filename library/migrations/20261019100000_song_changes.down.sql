@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP TRIGGER song_changes_delete;
+DROP TRIGGER song_changes_update;
+DROP TRIGGER song_changes_insert;
+DROP TABLE change_cursors;
+DROP TABLE song_changes;
@@ -0,0 +1,30 @@
+-- Add up migration script here
+-- AUTOINCREMENT so ids keep growing after compaction, they're the cursors consumers store
+CREATE TABLE song_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL CHECK (kind IN ('insert', 'update', 'delete')),
+    song_id INTEGER NOT NULL,
+    artist TEXT NOT NULL,
+    title TEXT NOT NULL
+);
+
+CREATE TABLE change_cursors (
+    consumer TEXT PRIMARY KEY,
+    position INTEGER NOT NULL
+);
+
+CREATE TRIGGER song_changes_insert AFTER INSERT ON songs BEGIN
+    INSERT INTO song_changes (kind, song_id, artist, title)
+    VALUES ('insert', new.id, new.artist, new.title);
+END;
+
+CREATE TRIGGER song_changes_update AFTER UPDATE ON songs BEGIN
+    INSERT INTO song_changes (kind, song_id, artist, title)
+    VALUES ('update', new.id, new.artist, new.title);
+END;
+
+-- deletes carry the values the song had
+CREATE TRIGGER song_changes_delete AFTER DELETE ON songs BEGIN
+    INSERT INTO song_changes (kind, song_id, artist, title)
+    VALUES ('delete', old.id, old.artist, old.title);
+END;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE dead_jobs;
+DROP TABLE jobs;
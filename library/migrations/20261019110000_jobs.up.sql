@@ -0,0 +1,26 @@
+-- Add up migration script here
+-- times are unix milliseconds
+-- AUTOINCREMENT so a deleted job's id is never handed out again, since dead_jobs keeps it
+CREATE TABLE jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    run_at INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    locked_by TEXT,
+    locked_until INTEGER
+);
+
+CREATE INDEX jobs_ready ON jobs (queue, priority DESC, run_at);
+
+CREATE TABLE dead_jobs (
+    id INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    priority INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    error TEXT NOT NULL,
+    failed_at INTEGER NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- keys and values are JSON, expires_at is unix milliseconds
+CREATE TABLE cache_entries (
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (namespace, key)
+) WITHOUT ROWID;
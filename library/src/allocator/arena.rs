@@ -0,0 +1,325 @@
+use std::{
+    alloc::{self, Layout},
+    cell::{Cell, RefCell},
+    mem,
+    ptr::{self, NonNull},
+    slice, str,
+};
+
+use super::AllocStats;
+
+const DEFAULT_CHUNK: usize = 4096;
+const CHUNK_ALIGN: usize = 16;
+
+/// A bump allocator for data that lives and dies together, like everything parsed out of one
+/// request.
+///
+/// Allocating is a pointer bump into the current chunk, and nothing is freed one at a time:
+/// [`Arena::reset`] hands all of it back at once and keeps the chunks for next time. Chunks
+/// come from the global allocator, so a global [`TrackingAlloc`](super::TrackingAlloc) counts
+/// them, and [`Arena::stats`] counts what was handed out of them.
+///
+/// Values from [`Arena::alloc`] are never dropped, which is right for plain data and leaks
+/// anything that owns heap memory. Use [`Arena::alloc_with_drop`] for those.
+///
+/// ```
+/// use rust_learning::allocator::Arena;
+///
+/// let mut arena = Arena::new();
+/// let artist = arena.alloc_str("Kenshi Yonezu");
+/// let plays = arena.alloc_slice_copy(&[3_u32, 1, 4]);
+/// assert_eq!((&*artist, &*plays), ("Kenshi Yonezu", &[3, 1, 4][..]));
+///
+/// arena.reset();
+/// assert_eq!(arena.stats().live_bytes, 0);
+/// ```
+#[derive(Debug)]
+pub struct Arena {
+    chunks: RefCell<Vec<Chunk>>,
+    // where the next allocation goes: chunk `current`, `used` bytes in
+    current: Cell<usize>,
+    used: Cell<usize>,
+    drops: RefCell<Vec<PendingDrop>>,
+    stats: Cell<AllocStats>,
+    // allocations since the last reset, which a reset counts as freed
+    live_allocations: Cell<u64>,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+#[derive(Debug)]
+struct PendingDrop {
+    ptr: NonNull<u8>,
+    drop: unsafe fn(*mut u8),
+}
+
+unsafe fn drop_as<T>(ptr: *mut u8) {
+    unsafe { ptr::drop_in_place(ptr.cast::<T>()) };
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    /// Allocates nothing until the first value comes in.
+    pub fn new() -> Self {
+        Self {
+            chunks: RefCell::new(Vec::new()),
+            current: Cell::new(0),
+            used: Cell::new(0),
+            drops: RefCell::new(Vec::new()),
+            stats: Cell::new(AllocStats::default()),
+            live_allocations: Cell::new(0),
+        }
+    }
+
+    /// Starts with a chunk of `bytes`, for when you know roughly how much a request takes.
+    pub fn with_capacity(bytes: usize) -> Self {
+        let arena = Self::new();
+        if bytes > 0 {
+            arena.chunks.borrow_mut().push(Chunk::new(bytes));
+        }
+        arena
+    }
+
+    /// Moves `value` into the arena. It's never dropped, see [`Arena::alloc_with_drop`].
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()).cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(value);
+            &mut *ptr.as_ptr()
+        }
+    }
+
+    /// Moves `value` into the arena and drops it on [`Arena::reset`] or when the arena goes.
+    ///
+    /// Only for values that don't borrow anything: the drop runs after whatever they borrowed,
+    /// a local or another value in the arena, might be gone.
+    ///
+    /// ```compile_fail
+    /// use rust_learning::allocator::Arena;
+    ///
+    /// struct Reader<'a>(&'a str);
+    ///
+    /// impl Drop for Reader<'_> {
+    ///     fn drop(&mut self) {
+    ///         println!("{}", self.0);
+    ///     }
+    /// }
+    ///
+    /// let arena = Arena::new();
+    /// {
+    ///     let s = String::from("gone by the time the arena drops");
+    ///     arena.alloc_with_drop(Reader(&s));
+    /// }
+    /// drop(arena);
+    /// ```
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_with_drop<T: 'static>(&self, value: T) -> &mut T {
+        let value = self.alloc(value);
+        if mem::needs_drop::<T>() {
+            self.drops.borrow_mut().push(PendingDrop {
+                ptr: NonNull::from(&mut *value).cast(),
+                drop: drop_as::<T>,
+            });
+        }
+        value
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(values);
+        let ptr = self.alloc_layout(layout).cast::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(values.as_ptr(), ptr.as_ptr(), values.len());
+            slice::from_raw_parts_mut(ptr.as_ptr(), values.len())
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, s: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(s.as_bytes());
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Drops what was allocated with [`Arena::alloc_with_drop`] and makes all the memory
+    /// available again. Without pending drops this is O(1), the chunks are kept as they are.
+    pub fn reset(&mut self) {
+        self.run_drops();
+        self.current.set(0);
+        self.used.set(0);
+        let mut stats = self.stats.get();
+        stats.deallocations += self.live_allocations.replace(0);
+        stats.bytes_freed += stats.live_bytes;
+        stats.live_bytes = 0;
+        self.stats.set(stats);
+    }
+
+    /// Bytes in all the chunks, used or not.
+    pub fn capacity(&self) -> usize {
+        self.chunks
+            .borrow()
+            .iter()
+            .map(|chunk| chunk.layout.size())
+            .sum()
+    }
+
+    /// What the arena handed out, counting a reset as freeing everything allocated since the
+    /// last one. Alignment padding isn't counted.
+    pub fn stats(&self) -> AllocStats {
+        self.stats.get()
+    }
+
+    fn record(&self, size: usize) {
+        let mut stats = self.stats.get();
+        stats.allocations += 1;
+        stats.bytes_allocated += size as u64;
+        stats.live_bytes += size as u64;
+        stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+        self.stats.set(stats);
+        self.live_allocations.set(self.live_allocations.get() + 1);
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        self.record(layout.size());
+        if layout.size() == 0 {
+            return NonNull::new(ptr::without_provenance_mut(layout.align())).unwrap();
+        }
+
+        let mut chunks = self.chunks.borrow_mut();
+        let mut index = self.current.get();
+        let mut used = self.used.get();
+        loop {
+            if let Some(chunk) = chunks.get(index) {
+                if let Some(offset) = chunk.fit(used, layout) {
+                    self.current.set(index);
+                    self.used.set(offset + layout.size());
+                    return unsafe { chunk.ptr.add(offset) };
+                }
+                // later chunks are kept from before a reset, and they're bigger
+                index += 1;
+                used = 0;
+                continue;
+            }
+            let last = chunks.last().map_or(DEFAULT_CHUNK / 2, |c| c.layout.size());
+            chunks.push(Chunk::new((last * 2).max(layout.size() + layout.align())));
+        }
+    }
+
+    fn run_drops(&mut self) {
+        for pending in self.drops.get_mut().drain(..) {
+            unsafe { (pending.drop)(pending.ptr.as_ptr()) };
+        }
+    }
+}
+
+impl Chunk {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, CHUNK_ALIGN).expect("arena chunk too big");
+        let ptr = NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+
+    // the offset `layout` would go at with `used` bytes taken, if it fits
+    fn fit(&self, used: usize, layout: Layout) -> Option<usize> {
+        let address = self.ptr.as_ptr() as usize + used;
+        let offset = used + (address.next_multiple_of(layout.align()) - address);
+        (offset + layout.size() <= self.layout.size()).then_some(offset)
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.run_drops();
+        for chunk in self.chunks.get_mut().drain(..) {
+            unsafe { alloc::dealloc(chunk.ptr.as_ptr(), chunk.layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::rc::Rc;
+
+    #[test]
+    fn hands_out_aligned_disjoint_values() {
+        let arena = Arena::with_capacity(64);
+        let byte = arena.alloc(1_u8);
+        let wide = arena.alloc(2_u128);
+        let words = arena.alloc_slice_copy(&[3_u64; 100]);
+        let title = arena.alloc_str("Lemon");
+
+        assert_eq!(wide as *mut u128 as usize % mem::align_of::<u128>(), 0);
+        *byte += 1;
+        words[99] = 4;
+        assert_eq!((*byte, *wide, words[0], words[99]), (2, 2, 3, 4));
+        assert_eq!(&*title, "Lemon");
+        assert!(arena.capacity() > 64);
+
+        let stats = arena.stats();
+        assert_eq!(stats.allocations, 4);
+        assert_eq!(stats.bytes_allocated, 1 + 16 + 800 + 5);
+    }
+
+    #[test]
+    fn reset_reuses_the_chunks() {
+        let mut arena = Arena::new();
+        for i in 0..1000_u64 {
+            arena.alloc(i);
+        }
+        let capacity = arena.capacity();
+        let first = arena.alloc(0_u64) as *mut u64;
+
+        arena.reset();
+        for i in 0..1000_u64 {
+            arena.alloc(i);
+        }
+        assert_eq!(arena.capacity(), capacity);
+
+        arena.reset();
+        let again = arena.alloc(0_u64) as *mut u64;
+        assert_ne!(first, again);
+        assert_eq!(arena.chunks.borrow()[0].ptr.as_ptr().cast(), again);
+
+        let stats = arena.stats();
+        assert_eq!((stats.allocations, stats.deallocations), (2002, 2001));
+        assert_eq!((stats.live_bytes, stats.peak_bytes), (8, 8008));
+    }
+
+    #[test]
+    fn drops_only_when_asked() {
+        let counted = Rc::new(());
+        let mut arena = Arena::new();
+        arena.alloc(Rc::clone(&counted));
+        arena.alloc_with_drop(Rc::clone(&counted));
+        arena.alloc_with_drop(vec![Rc::clone(&counted)]);
+        assert_eq!(Rc::strong_count(&counted), 4);
+
+        arena.reset();
+        assert_eq!(Rc::strong_count(&counted), 2);
+
+        arena.alloc_with_drop(Rc::clone(&counted));
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counted), 2);
+    }
+
+    #[test]
+    fn zero_sized_values_take_no_space() {
+        let arena = Arena::new();
+        arena.alloc(());
+        arena.alloc_slice_copy::<u32>(&[]);
+        assert_eq!(&*arena.alloc_str(""), "");
+        assert_eq!(arena.capacity(), 0);
+    }
+}
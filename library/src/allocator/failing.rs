@@ -0,0 +1,350 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Which allocations a [`FailingAlloc`] refuses inside [`FailingAlloc::inject`]. Set more than
+/// one and an allocation fails if any of them says so.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Faults {
+    after: Option<u64>,
+    budget: Option<usize>,
+    probability: f64,
+    seed: u64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Faults {
+    /// Fails nothing.
+    pub const fn new() -> Self {
+        Self {
+            after: None,
+            budget: None,
+            probability: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Lets `allocations` through and fails every one after.
+    pub const fn fail_after(mut self, allocations: u64) -> Self {
+        self.after = Some(allocations);
+        self
+    }
+
+    /// Fails anything that would take the bytes allocated and not yet freed in the scope over
+    /// `bytes`.
+    pub const fn byte_budget(mut self, bytes: usize) -> Self {
+        self.budget = Some(bytes);
+        self
+    }
+
+    /// Fails each allocation with this probability, from a generator seeded with `seed` so a
+    /// failing run can be repeated.
+    pub const fn probability(mut self, probability: f64, seed: u64) -> Self {
+        self.probability = probability;
+        self.seed = seed;
+        self
+    }
+}
+
+// The current thread's plan, const-initialized so the allocator can read it without allocating.
+struct Plan {
+    active: Cell<bool>,
+    remaining: Cell<u64>,
+    budget: Cell<usize>,
+    live: Cell<isize>,
+    threshold: Cell<u64>,
+    rng: Cell<u64>,
+    failed: Cell<u64>,
+}
+
+thread_local! {
+    static PLAN: Plan = const {
+        Plan {
+            active: Cell::new(false),
+            remaining: Cell::new(u64::MAX),
+            budget: Cell::new(usize::MAX),
+            live: Cell::new(0),
+            threshold: Cell::new(0),
+            rng: Cell::new(0),
+            failed: Cell::new(0),
+        }
+    };
+}
+
+type Saved = (bool, u64, usize, isize, u64, u64, u64);
+
+impl Plan {
+    fn save(&self) -> Saved {
+        (
+            self.active.get(),
+            self.remaining.get(),
+            self.budget.get(),
+            self.live.get(),
+            self.threshold.get(),
+            self.rng.get(),
+            self.failed.get(),
+        )
+    }
+
+    fn restore(&self, (active, remaining, budget, live, threshold, rng, failed): Saved) {
+        self.active.set(active);
+        self.remaining.set(remaining);
+        self.budget.set(budget);
+        self.live.set(live);
+        self.threshold.set(threshold);
+        self.rng.set(rng);
+        self.failed.set(failed);
+    }
+
+    fn start(&self, faults: Faults) {
+        let threshold = if faults.probability >= 1.0 {
+            u64::MAX
+        } else {
+            (faults.probability.max(0.0) * u64::MAX as f64) as u64
+        };
+        self.active.set(true);
+        self.remaining.set(faults.after.unwrap_or(u64::MAX));
+        self.budget.set(faults.budget.unwrap_or(usize::MAX));
+        self.live.set(0);
+        self.threshold.set(threshold);
+        // xorshift never leaves 0
+        self.rng.set(faults.seed | 1);
+        self.failed.set(0);
+    }
+
+    fn random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+
+    // whether an allocation that adds `grow` live bytes may go ahead, counting it if so
+    fn admit(&self, grow: usize) -> bool {
+        if !self.active.get() {
+            return true;
+        }
+        let live = self.live.get().max(0) as usize;
+        let refused = self.remaining.get() == 0
+            || live.saturating_add(grow) > self.budget.get()
+            || (self.threshold.get() != 0 && self.random() < self.threshold.get());
+        if refused {
+            self.failed.set(self.failed.get() + 1);
+            return false;
+        }
+        self.remaining.set(self.remaining.get().saturating_sub(1));
+        self.live.set(self.live.get() + grow as isize);
+        true
+    }
+
+    fn release(&self, shrink: usize) {
+        if self.active.get() {
+            self.live.set(self.live.get() - shrink as isize);
+        }
+    }
+}
+
+// puts the outer plan back even if the scope panics
+struct Restore(Saved);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = PLAN.try_with(|plan| plan.restore(self.0));
+    }
+}
+
+/// Wraps an allocator and returns null when told to, to exercise out-of-memory handling.
+///
+/// Outside [`FailingAlloc::inject`] it just passes calls through. Inside, only the current
+/// thread's allocations are affected, so tests running in parallel don't fail each other's.
+/// Anything infallible that allocates in the scope, a `Vec::push` or a `format!`, aborts the
+/// process when it's refused, or hangs it if the test harness is capturing output, so keep the
+/// scope to `try_reserve` and friends. That includes building errors: convert them after.
+///
+/// ```
+/// use std::alloc::System;
+/// use rust_learning::allocator::{FailingAlloc, Faults};
+///
+/// #[global_allocator]
+/// static ALLOC: FailingAlloc<System> = FailingAlloc::new(System);
+///
+/// fn main() {
+///     let mut v = Vec::<u8>::new();
+///     let (reserved, failed) = ALLOC.inject(Faults::new().byte_budget(100), || v.try_reserve(1000));
+///     assert!(reserved.is_err());
+///     assert_eq!(failed, 1);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FailingAlloc<A = System> {
+    inner: A,
+    failures: AtomicUsize,
+}
+
+impl<A> FailingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Allocations refused on any thread since the allocator was created.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with `faults` applied to this thread, and returns how many allocations were
+    /// refused. Scopes nest, the inner one replacing the outer until it ends.
+    ///
+    /// Like [`TrackingAlloc::measure`](super::TrackingAlloc::measure), the plan is per thread
+    /// rather than per allocator.
+    pub fn inject<R>(&self, faults: Faults, f: impl FnOnce() -> R) -> (R, u64) {
+        let restore = PLAN.with(|plan| {
+            let saved = plan.save();
+            plan.start(faults);
+            Restore(saved)
+        });
+        let result = f();
+        let failed = PLAN.with(|plan| plan.failed.get());
+        drop(restore);
+        (result, failed)
+    }
+
+    fn admit(&self, grow: usize) -> bool {
+        let admitted = PLAN.try_with(|plan| plan.admit(grow)).unwrap_or(true);
+        if !admitted {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        admitted
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for FailingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !self.admit(layout.size()) {
+            return ptr::null_mut();
+        }
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !self.admit(layout.size()) {
+            return ptr::null_mut();
+        }
+        unsafe { self.inner.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = PLAN.try_with(|plan| plan.release(layout.size()));
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    // counts as one allocation, of however much it grows by
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.admit(new_size.saturating_sub(layout.size())) {
+            return ptr::null_mut();
+        }
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            let _ = PLAN.try_with(|plan| plan.release(layout.size().saturating_sub(new_size)));
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn try_alloc(alloc: &FailingAlloc, size: usize) -> bool {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { alloc.alloc(layout) };
+        if ptr.is_null() {
+            return false;
+        }
+        unsafe { alloc.dealloc(ptr, layout) };
+        true
+    }
+
+    #[test]
+    fn fails_after_n_allocations() {
+        let alloc = FailingAlloc::new(System);
+        let (results, failed) = alloc.inject(Faults::new().fail_after(2), || {
+            [(); 4].map(|()| try_alloc(&alloc, 8))
+        });
+
+        assert_eq!(results, [true, true, false, false]);
+        assert_eq!(failed, 2);
+        assert_eq!(alloc.failures(), 2);
+        assert!(try_alloc(&alloc, 8));
+    }
+
+    #[test]
+    fn budgets_live_bytes() {
+        let alloc = FailingAlloc::new(System);
+        let layout = Layout::from_size_align(60, 8).unwrap();
+
+        let ((), failed) = alloc.inject(Faults::new().byte_budget(100), || unsafe {
+            let kept = alloc.alloc(layout);
+            assert!(!kept.is_null());
+            assert!(!try_alloc(&alloc, 60));
+            assert!(try_alloc(&alloc, 40));
+
+            // growing counts against the budget, shrinking gives it back
+            assert!(alloc.realloc(kept, layout, 200).is_null());
+            let kept = alloc.realloc(kept, layout, 20);
+            assert!(try_alloc(&alloc, 80));
+            alloc.dealloc(kept, Layout::from_size_align(20, 8).unwrap());
+        });
+        assert_eq!(failed, 2);
+    }
+
+    #[test]
+    fn seeded_failures_repeat() {
+        let alloc = FailingAlloc::new(System);
+        let run = |seed| {
+            alloc
+                .inject(Faults::new().probability(0.5, seed), || {
+                    [(); 64].map(|()| try_alloc(&alloc, 8))
+                })
+                .0
+        };
+
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert_ne!(first, run(8));
+        let failed = first.iter().filter(|&&ok| !ok).count();
+        assert!((16..48).contains(&failed), "{failed}");
+    }
+
+    #[test]
+    fn scopes_nest_and_restore() {
+        let alloc = FailingAlloc::new(System);
+        let ((inner, after_inner), outer_failed) =
+            alloc.inject(Faults::new().fail_after(1), || {
+                let inner = alloc.inject(Faults::new(), || try_alloc(&alloc, 8));
+                (inner, try_alloc(&alloc, 8))
+            });
+
+        assert_eq!(inner, (true, 0));
+        // the inner scope's allocation didn't use up the outer one's
+        assert!(after_inner);
+        assert_eq!(outer_failed, 0);
+    }
+}
@@ -0,0 +1,16 @@
+//! Allocators that wrap another [`GlobalAlloc`](std::alloc::GlobalAlloc) to observe or change
+//! how memory is handed out.
+
+mod arena;
+mod failing;
+mod profile;
+#[cfg(unix)]
+mod secure;
+mod tracking;
+
+pub use arena::Arena;
+pub use failing::{FailingAlloc, Faults};
+pub use profile::{HeapProfiler, HeapReport, SiteReport};
+#[cfg(unix)]
+pub use secure::{SecretBox, SecureAlloc};
+pub use tracking::{AllocStats, TrackingAlloc};
@@ -0,0 +1,453 @@
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::BTreeMap,
+    ffi::c_void,
+    fmt::Write as _,
+    io,
+    path::Path,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+};
+
+/// Frames kept per sampled allocation, innermost first.
+const MAX_DEPTH: usize = 48;
+// sampled pointers are counted into these buckets, so most frees can tell they weren't sampled
+// without taking the lock
+const FILTER_SLOTS: usize = 4096;
+
+/// Samples allocations with their backtraces, for a [`TrackingAlloc`](super::TrackingAlloc)
+/// set up with [`with_profiler`](super::TrackingAlloc::with_profiler).
+///
+/// Off until [`set_sample_rate`](Self::set_sample_rate) is called. The backtraces are captured
+/// as raw addresses, and only symbolized when a [`report`](Self::report) is made.
+///
+/// To profile a whole run, turn it on and dump the report with the `ctor`/`dtor` hooks:
+///
+/// ```
+/// use std::alloc::System;
+/// use rust_learning::allocator::{HeapProfiler, TrackingAlloc};
+///
+/// static PROFILER: HeapProfiler = HeapProfiler::new();
+///
+/// #[global_allocator]
+/// static ALLOC: TrackingAlloc = TrackingAlloc::new(System).with_profiler(&PROFILER);
+///
+/// // HEAP_PROFILE_RATE=0.01 HEAP_PROFILE=/tmp/heap cargo run
+/// #[ctor::ctor]
+/// fn start_profiling() {
+///     PROFILER.configure_from_env();
+/// }
+///
+/// #[ctor::dtor]
+/// fn dump_profile() {
+///     PROFILER.dump_from_env();
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct HeapProfiler {
+    // a sample is taken when a random u64 falls below this
+    threshold: AtomicU64,
+    filter: [AtomicU32; FILTER_SLOTS],
+    profile: Mutex<Profile>,
+}
+
+#[derive(Debug)]
+struct Profile {
+    live: BTreeMap<usize, Sample>,
+    sites: Vec<(Vec<usize>, Site)>,
+    site_ids: BTreeMap<Vec<usize>, usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    site: usize,
+    size: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Site {
+    live_bytes: u64,
+    live_allocations: u64,
+    total_bytes: u64,
+    total_allocations: u64,
+}
+
+thread_local! {
+    // set while the profiler itself runs, so its own allocations aren't sampled
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+    static RNG: Cell<u64> = const { Cell::new(0x9e37_79b9_7f4a_7c15) };
+}
+
+// Runs `f` unless this thread is already inside the profiler.
+fn exclusive<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let entered = BUSY.try_with(|busy| !busy.replace(true)).unwrap_or(false);
+    if !entered {
+        return None;
+    }
+    let result = f();
+    let _ = BUSY.try_with(|busy| busy.set(false));
+    Some(result)
+}
+
+fn slot(ptr: usize) -> usize {
+    ((ptr as u64 >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 52) as usize % FILTER_SLOTS
+}
+
+// xorshift, mixed with the address so threads that start with the same state drift apart
+fn random(ptr: usize) -> u64 {
+    RNG.try_with(|rng| {
+        let mut x = rng.get() ^ ptr as u64;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        x
+    })
+    .unwrap_or(u64::MAX)
+}
+
+impl Default for HeapProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapProfiler {
+    pub const fn new() -> Self {
+        Self {
+            threshold: AtomicU64::new(0),
+            filter: [const { AtomicU32::new(0) }; FILTER_SLOTS],
+            profile: Mutex::new(Profile {
+                live: BTreeMap::new(),
+                sites: Vec::new(),
+                site_ids: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Samples this fraction of allocations, from 0 (off) to 1 (all of them).
+    pub fn set_sample_rate(&self, rate: f64) {
+        let threshold = if rate >= 1.0 {
+            u64::MAX
+        } else {
+            (rate.max(0.0) * u64::MAX as f64) as u64
+        };
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.threshold.load(Ordering::Relaxed) as f64 / u64::MAX as f64
+    }
+
+    /// Reads the sample rate from `HEAP_PROFILE_RATE`, if it's set.
+    pub fn configure_from_env(&self) {
+        if let Some(rate) = std::env::var("HEAP_PROFILE_RATE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+        {
+            self.set_sample_rate(rate);
+        }
+    }
+
+    /// Forgets everything sampled so far.
+    pub fn clear(&self) {
+        exclusive(|| {
+            let mut profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+            profile.live.clear();
+            profile.sites.clear();
+            profile.site_ids.clear();
+            for slot in &self.filter {
+                slot.store(0, Ordering::Relaxed);
+            }
+        });
+    }
+
+    pub(super) fn allocated(&self, ptr: *mut u8, size: usize) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        if threshold == 0 || (threshold != u64::MAX && random(ptr as usize) >= threshold) {
+            return;
+        }
+        exclusive(|| {
+            let mut stack = [0_usize; MAX_DEPTH];
+            let mut depth = 0;
+            backtrace::trace(|frame| {
+                stack[depth] = frame.ip() as usize;
+                depth += 1;
+                depth < MAX_DEPTH
+            });
+
+            let mut profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+            let site = profile.site(&stack[..depth]);
+            let stats = &mut profile.sites[site].1;
+            stats.live_bytes += size as u64;
+            stats.live_allocations += 1;
+            stats.total_bytes += size as u64;
+            stats.total_allocations += 1;
+            profile.live.insert(ptr as usize, Sample { site, size });
+            self.filter[slot(ptr as usize)].fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    // has to run before the memory goes back to the allocator, or another thread could get
+    // the same address and have its sample dropped here
+    pub(super) fn freed(&self, ptr: *mut u8) {
+        let slot = &self.filter[slot(ptr as usize)];
+        if slot.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        exclusive(|| {
+            let mut profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(sample) = profile.live.remove(&(ptr as usize)) {
+                let stats = &mut profile.sites[sample.site].1;
+                stats.live_bytes -= sample.size as u64;
+                stats.live_allocations -= 1;
+                slot.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// Symbolizes what's been sampled, grouped by backtrace, most live bytes first.
+    pub fn report(&self) -> HeapReport {
+        let sample_rate = self.sample_rate();
+        exclusive(|| {
+            let sites = self
+                .profile
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .sites
+                .clone();
+
+            let mut names = BTreeMap::new();
+            let mut sites = sites
+                .into_iter()
+                .map(|(stack, site)| SiteReport {
+                    frames: trim(
+                        stack
+                            .iter()
+                            .flat_map(|&ip| {
+                                names.entry(ip).or_insert_with(|| symbolize(ip)).clone()
+                            })
+                            .collect(),
+                    ),
+                    live_bytes: site.live_bytes,
+                    live_allocations: site.live_allocations,
+                    total_bytes: site.total_bytes,
+                    total_allocations: site.total_allocations,
+                })
+                .collect::<Vec<_>>();
+            sites.sort_by_key(|site| Reverse((site.live_bytes, site.total_bytes)));
+            HeapReport { sample_rate, sites }
+        })
+        .unwrap_or(HeapReport {
+            sample_rate,
+            sites: Vec::new(),
+        })
+    }
+
+    /// Writes the report to `<prefix>.txt` and, for flamegraphs, `<prefix>.folded`.
+    pub fn dump(&self, prefix: impl AsRef<Path>) -> io::Result<()> {
+        let report = self.report();
+        let prefix = prefix.as_ref();
+        std::fs::write(prefix.with_extension("txt"), report.to_text())?;
+        std::fs::write(prefix.with_extension("folded"), report.to_collapsed())
+    }
+
+    /// [`dump`](Self::dump)s to the prefix in `HEAP_PROFILE`, if it's set. Meant for a `dtor`,
+    /// where there's nobody to return an error to, so failures go to stderr.
+    pub fn dump_from_env(&self) {
+        if let Some(prefix) = std::env::var_os("HEAP_PROFILE")
+            && let Err(e) = self.dump(&prefix)
+        {
+            eprintln!("failed to write heap profile to {}: {e}", prefix.display());
+        }
+    }
+}
+
+impl Profile {
+    fn site(&mut self, stack: &[usize]) -> usize {
+        if let Some(&id) = self.site_ids.get(stack) {
+            return id;
+        }
+        let id = self.sites.len();
+        self.sites.push((stack.to_vec(), Site::default()));
+        self.site_ids.insert(stack.to_vec(), id);
+        id
+    }
+}
+
+// one name per frame, more than one where calls were inlined
+fn symbolize(ip: usize) -> Vec<String> {
+    let mut names = Vec::new();
+    backtrace::resolve(ip as *mut c_void, |symbol| {
+        names.push(match symbol.name() {
+            Some(name) => format!("{name:#}"),
+            None => format!("{ip:#x}"),
+        });
+    });
+    if names.is_empty() {
+        names.push(format!("{ip:#x}"));
+    }
+    names
+}
+
+// drops the frames of the backtrace, the profiler and the allocator shims from the top
+fn trim(frames: Vec<String>) -> Vec<String> {
+    let internal = |name: &str| {
+        name.starts_with("backtrace::")
+            || name.contains("rust_learning::allocator::profile::exclusive")
+            || name.contains("rust_learning::allocator::profile::HeapProfiler")
+            || name.contains("rust_learning::allocator::tracking::TrackingAlloc")
+            || name.starts_with("__rust")
+            || name.starts_with("__rg_")
+            || name.starts_with("__rdl_")
+    };
+    frames
+        .into_iter()
+        .skip_while(|name| internal(name))
+        .collect()
+}
+
+/// Allocations that share a backtrace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteReport {
+    /// Function names, innermost first.
+    pub frames: Vec<String>,
+    pub live_bytes: u64,
+    pub live_allocations: u64,
+    pub total_bytes: u64,
+    pub total_allocations: u64,
+}
+
+/// Sampled allocations by call site. The numbers only cover what was sampled, divide by
+/// `sample_rate` for an estimate of the whole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapReport {
+    pub sample_rate: f64,
+    pub sites: Vec<SiteReport>,
+}
+
+impl HeapReport {
+    /// Sites that still hold memory.
+    pub fn live(&self) -> impl Iterator<Item = &SiteReport> {
+        self.sites.iter().filter(|site| site.live_bytes > 0)
+    }
+
+    pub fn to_text(&self) -> String {
+        let live_bytes: u64 = self.sites.iter().map(|site| site.live_bytes).sum();
+        let live_allocations: u64 = self.sites.iter().map(|site| site.live_allocations).sum();
+        let mut out = format!(
+            "{live_bytes} live bytes in {live_allocations} allocations, sampling {}% of allocations\n",
+            self.sample_rate * 100.0
+        );
+        for site in self.live() {
+            let _ = writeln!(
+                out,
+                "\n{} bytes live in {} allocations ({} bytes in {} allocations in total)",
+                site.live_bytes, site.live_allocations, site.total_bytes, site.total_allocations
+            );
+            for frame in &site.frames {
+                let _ = writeln!(out, "    {frame}");
+            }
+        }
+        out
+    }
+
+    /// Live bytes in the collapsed stack format that `flamegraph.pl` and `inferno` read, one
+    /// `outermost;...;innermost bytes` line per site.
+    pub fn to_collapsed(&self) -> String {
+        let mut out = String::new();
+        for site in self.live() {
+            let stack = site
+                .frames
+                .iter()
+                .rev()
+                .map(|frame| frame.replace(';', ":"))
+                .collect::<Vec<_>>()
+                .join(";");
+            let _ = writeln!(out, "{stack} {}", site.live_bytes);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::TrackingAlloc;
+    use pretty_assertions::assert_eq;
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    #[inline(never)]
+    fn leaky_site(alloc: &TrackingAlloc<System>, layout: Layout) -> *mut u8 {
+        unsafe { alloc.alloc(layout) }
+    }
+
+    #[test]
+    fn attributes_live_bytes_to_call_sites() {
+        static PROFILER: HeapProfiler = HeapProfiler::new();
+        let alloc = TrackingAlloc::new(System).with_profiler(&PROFILER);
+        let layout = Layout::from_size_align(256, 8).unwrap();
+
+        let unsampled = leaky_site(&alloc, layout);
+        PROFILER.set_sample_rate(1.0);
+        // from one call site, so both land in the same stack
+        let [kept, freed] = [(); 2].map(|()| leaky_site(&alloc, layout));
+        unsafe {
+            alloc.dealloc(freed, layout);
+            alloc.dealloc(unsampled, layout);
+        }
+
+        let report = PROFILER.report();
+        assert_eq!(report.sample_rate, 1.0);
+        let live = report.live().collect::<Vec<_>>();
+        assert_eq!(live.len(), 1);
+        assert_eq!((live[0].live_bytes, live[0].live_allocations), (256, 1));
+        assert_eq!((live[0].total_bytes, live[0].total_allocations), (512, 2));
+        assert!(
+            live[0].frames[0].ends_with("leaky_site"),
+            "{:?}",
+            live[0].frames
+        );
+
+        let text = report.to_text();
+        assert!(text.starts_with("256 live bytes in 1 allocations, sampling 100% of allocations"));
+        let collapsed = report.to_collapsed();
+        assert!(
+            collapsed.trim_end().ends_with("leaky_site 256"),
+            "{collapsed}"
+        );
+
+        unsafe { alloc.dealloc(kept, layout) };
+        assert_eq!(PROFILER.report().live().count(), 0);
+        PROFILER.clear();
+        assert!(PROFILER.report().sites.is_empty());
+    }
+
+    #[test]
+    fn samples_a_fraction() {
+        static PROFILER: HeapProfiler = HeapProfiler::new();
+        let alloc = TrackingAlloc::new(System).with_profiler(&PROFILER);
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        PROFILER.set_sample_rate(0.25);
+
+        let ptrs = (0..2000)
+            .map(|_| leaky_site(&alloc, layout))
+            .collect::<Vec<_>>();
+        let sampled: u64 = PROFILER
+            .report()
+            .sites
+            .iter()
+            .map(|s| s.live_allocations)
+            .sum();
+        for ptr in ptrs {
+            unsafe { alloc.dealloc(ptr, layout) };
+        }
+
+        assert!((300..700).contains(&sampled), "{sampled}");
+        assert_eq!(PROFILER.report().live().count(), 0);
+    }
+}
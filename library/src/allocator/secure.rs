@@ -0,0 +1,445 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fmt,
+    marker::PhantomData,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// An allocator for memory that holds secrets.
+///
+/// Everything it frees is zeroed first, including the old block when a `realloc` moves, which
+/// is where a plain zeroing `dealloc` leaks: the inner allocator's own realloc copies and frees
+/// without asking. On top of that it can
+///
+/// - `mlock` what it hands out so it's never swapped to disk, see [`SecureAlloc::with_mlock`]
+/// - put allocations from a size up in their own mapping between two `PROT_NONE` pages, so
+///   running off either end faults instead of reading a neighbour, see
+///   [`SecureAlloc::with_guard_pages`]
+///
+/// Both are best effort: a failed `mlock`, usually from `RLIMIT_MEMLOCK`, still hands out the
+/// memory and is counted in [`SecureAlloc::lock_failures`].
+#[derive(Debug)]
+pub struct SecureAlloc<A = System> {
+    inner: A,
+    mlock: bool,
+    guard_from: usize,
+    lock_failures: AtomicUsize,
+}
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn page_size() -> usize {
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            PAGE_SIZE.store(size, Ordering::Relaxed);
+            size
+        }
+        size => size,
+    }
+}
+
+fn round_up(size: usize, to: usize) -> usize {
+    size.div_ceil(to) * to
+}
+
+/// Overwrites `len` bytes at `ptr` with zeros the compiler can't drop as dead stores.
+///
+/// # Safety
+///
+/// `ptr` has to be valid for `len` bytes of writes.
+unsafe fn zeroize(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        unsafe { ptr::write_volatile(ptr.add(i), 0) };
+    }
+}
+
+// Where a guarded allocation sits: `len` readable bytes starting at `start`, one guard page
+// below and one above, with the data pushed up against the upper guard.
+struct Guarded {
+    start: usize,
+    len: usize,
+    offset: usize,
+}
+
+impl Guarded {
+    fn new(layout: Layout) -> Self {
+        let page = page_size();
+        let len = round_up(layout.size().max(1), page);
+        let offset = (len - layout.size()) & !(layout.align() - 1);
+        Self {
+            start: 0,
+            len,
+            offset,
+        }
+    }
+
+    fn mapping(&self) -> (*mut libc::c_void, usize) {
+        let page = page_size();
+        (
+            (self.start - page) as *mut libc::c_void,
+            self.len + 2 * page,
+        )
+    }
+}
+
+impl<A> SecureAlloc<A> {
+    /// Zeroes on free, without locking or guard pages.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            mlock: false,
+            guard_from: usize::MAX,
+            lock_failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Locks allocations into RAM. Small allocations share pages with others, so their pages
+    /// stay locked after they're freed, `munlock` can't tell whose they were.
+    pub const fn with_mlock(mut self, mlock: bool) -> Self {
+        self.mlock = mlock;
+        self
+    }
+
+    /// Maps allocations of `min_size` bytes and up separately, with a guard page on each side.
+    /// Each costs at least three pages and two syscalls, so keep this for large or sensitive
+    /// blocks; 0 guards everything.
+    pub const fn with_guard_pages(mut self, min_size: usize) -> Self {
+        self.guard_from = min_size;
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// How many times `mlock` was refused.
+    pub fn lock_failures(&self) -> usize {
+        self.lock_failures.load(Ordering::Relaxed)
+    }
+
+    fn guarded(&self, layout: Layout) -> bool {
+        layout.size() >= self.guard_from && layout.align() <= page_size()
+    }
+
+    fn lock(&self, ptr: *mut u8, len: usize) {
+        if !self.mlock || len == 0 {
+            return;
+        }
+        // mlock rounds out to whole pages itself
+        if unsafe { libc::mlock(ptr.cast(), len) } != 0 {
+            self.lock_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn map_guarded(&self, layout: Layout) -> *mut u8 {
+        let mut guarded = Guarded::new(layout);
+        let page = page_size();
+        let total = guarded.len + 2 * page;
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                total,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return ptr::null_mut();
+        }
+        guarded.start = base as usize + page;
+        let start = guarded.start as *mut libc::c_void;
+        if unsafe { libc::mprotect(start, guarded.len, libc::PROT_READ | libc::PROT_WRITE) } != 0 {
+            unsafe { libc::munmap(base, total) };
+            return ptr::null_mut();
+        }
+        self.lock(start.cast(), guarded.len);
+        (guarded.start + guarded.offset) as *mut u8
+    }
+
+    unsafe fn unmap_guarded(&self, ptr: *mut u8, layout: Layout) {
+        let mut guarded = Guarded::new(layout);
+        guarded.start = ptr as usize - guarded.offset;
+        unsafe { zeroize(guarded.start as *mut u8, guarded.len) };
+        if self.mlock {
+            unsafe { libc::munlock(guarded.start as *const libc::c_void, guarded.len) };
+        }
+        let (base, total) = guarded.mapping();
+        unsafe { libc::munmap(base, total) };
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for SecureAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.guarded(layout) {
+            return unsafe { self.map_guarded(layout) };
+        }
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.lock(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // fresh anonymous mappings are already zero
+        if self.guarded(layout) {
+            return unsafe { self.map_guarded(layout) };
+        }
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            self.lock(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.guarded(layout) {
+            return unsafe { self.unmap_guarded(ptr, layout) };
+        }
+        unsafe {
+            zeroize(ptr, layout.size());
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    // never the inner realloc, which would free the old block without zeroing it
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+static SECRETS: SecureAlloc = SecureAlloc::new(System)
+    .with_mlock(true)
+    .with_guard_pages(0);
+
+/// A value in its own locked, guarded mapping, zeroed when it's dropped.
+///
+/// Only the value's own bytes live in the mapping, so it's limited to `Copy` types like
+/// `[u8; 32]` that can't point at memory of their own: a `String` or `Vec` inside would keep
+/// its contents on the ordinary heap.
+///
+/// It doesn't print its contents and there's no `Deref`, reading it takes an explicit
+/// [`SecretBox::expose`]. Moving a value in with [`SecretBox::new`] can leave copies of it on
+/// the stack, [`SecretBox::init_with`] builds it in place instead.
+pub struct SecretBox<T: Copy> {
+    ptr: NonNull<T>,
+    _owns: PhantomData<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SecretBox<T> {}
+unsafe impl<T: Copy + Sync> Sync for SecretBox<T> {}
+
+impl<T: Copy> SecretBox<T> {
+    pub fn new(value: T) -> Self {
+        let secret = Self::allocate();
+        unsafe { secret.ptr.as_ptr().write(value) };
+        secret
+    }
+
+    /// Starts from `T::default()` and lets `init` fill it where it'll stay.
+    pub fn init_with(init: impl FnOnce(&mut T)) -> Self
+    where
+        T: Default,
+    {
+        let mut secret = Self::new(T::default());
+        init(secret.expose_mut());
+        secret
+    }
+
+    pub fn expose(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn expose_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+
+    // the memory isn't initialized until the caller writes to it, so this mustn't be dropped
+    // before then
+    fn allocate() -> Self {
+        let layout = Layout::new::<T>();
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            let ptr = unsafe { SECRETS.alloc(layout) }.cast::<T>();
+            NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(layout))
+        };
+        Self {
+            ptr,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy> Drop for SecretBox<T> {
+    fn drop(&mut self) {
+        // `Copy` types have nothing to drop, freeing is all there is
+        let layout = Layout::new::<T>();
+        if layout.size() != 0 {
+            unsafe { SECRETS.dealloc(self.ptr.as_ptr().cast(), layout) };
+        }
+    }
+}
+
+impl<T: Copy> fmt::Debug for SecretBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretBox(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{hint::black_box, sync::Mutex};
+
+    // remembers whether each block it got back was all zeros
+    #[derive(Default)]
+    struct Spy {
+        freed_clean: Mutex<Vec<bool>>,
+    }
+
+    unsafe impl GlobalAlloc for Spy {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+            let clean = bytes.iter().all(|&b| b == 0);
+            self.freed_clean.lock().unwrap().push(clean);
+            unsafe { System.dealloc(ptr, layout) };
+        }
+
+        unsafe fn realloc(&self, _: *mut u8, _: Layout, _: usize) -> *mut u8 {
+            panic!("SecureAlloc shouldn't use the inner realloc");
+        }
+    }
+
+    // runs `f` in a forked child and returns the signal that killed it, if any
+    fn signal_in_child(f: impl FnOnce()) -> Option<i32> {
+        unsafe {
+            match libc::fork() {
+                0 => {
+                    f();
+                    libc::_exit(0);
+                }
+                -1 => panic!("fork failed"),
+                child => {
+                    let mut status = 0;
+                    libc::waitpid(child, &mut status, 0);
+                    libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status))
+                }
+            }
+        }
+    }
+
+    fn memlock_limit() -> u64 {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) },
+            0
+        );
+        limit.rlim_cur
+    }
+
+    #[test]
+    fn zeroes_on_free_and_when_realloc_moves() {
+        let alloc = SecureAlloc::new(Spy::default());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc.alloc(layout);
+            ptr.write_bytes(0xAA, 32);
+            let ptr = alloc.realloc(ptr, layout, 4096);
+            assert_eq!(*ptr.add(31), 0xAA);
+            alloc.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+        }
+
+        assert_eq!(*alloc.inner().freed_clean.lock().unwrap(), [true, true]);
+    }
+
+    #[test]
+    fn guard_pages_fault_on_overruns() {
+        let alloc = SecureAlloc::new(System).with_guard_pages(1024);
+        let layout = Layout::from_size_align(5000, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc.alloc(layout);
+            assert_eq!(ptr as usize % 8, 0);
+            ptr.write_bytes(0xAA, 5000);
+            let end = ptr.add(5000);
+            let start = ptr.sub(ptr as usize % page_size());
+
+            // the data ends less than its alignment short of the upper guard
+            assert_eq!(
+                signal_in_child(|| end.add(7).write_volatile(1)),
+                Some(libc::SIGSEGV)
+            );
+            assert_eq!(
+                signal_in_child(|| {
+                    black_box(start.sub(1).read_volatile());
+                }),
+                Some(libc::SIGSEGV)
+            );
+            assert_eq!(
+                signal_in_child(|| {
+                    black_box(end.sub(1).read_volatile());
+                }),
+                None
+            );
+
+            alloc.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn guarded_zeroed_allocations_are_zero() {
+        let alloc = SecureAlloc::new(System)
+            .with_guard_pages(0)
+            .with_mlock(true);
+        let layout = Layout::from_size_align(100, 16).unwrap();
+        unsafe {
+            let ptr = alloc.alloc_zeroed(layout);
+            assert_eq!(ptr as usize % 16, 0);
+            assert!(std::slice::from_raw_parts(ptr, 100).iter().all(|&b| b == 0));
+            let grown = alloc.realloc(ptr, layout, 10_000);
+            alloc.dealloc(grown, Layout::from_size_align(10_000, 16).unwrap());
+        }
+        // a tight RLIMIT_MEMLOCK is shared with every other test locking memory at the time
+        if memlock_limit() >= 1024 * 1024 {
+            assert_eq!(alloc.lock_failures(), 0);
+        }
+    }
+
+    #[test]
+    fn secret_box_keeps_its_value_to_itself() {
+        let mut key = SecretBox::init_with(|key: &mut [u8; 32]| key.fill(7));
+        key.expose_mut()[0] = 1;
+        assert_eq!(key.expose()[..2], [1, 7]);
+        assert_eq!(format!("{key:?}"), "SecretBox(<redacted>)");
+
+        let pin = SecretBox::new(*b"1234");
+        assert_eq!(pin.expose(), b"1234");
+
+        // zero-sized values don't allocate
+        drop(SecretBox::new(()));
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::HeapProfiler;
+
+/// Counts what an allocator hands out, either since the start or inside [`TrackingAlloc::measure`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Calls to `alloc` and `alloc_zeroed`.
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub reallocations: u64,
+    /// Bytes handed out, a realloc counting its new size.
+    pub bytes_allocated: u64,
+    /// Bytes given back, a realloc counting its old size.
+    pub bytes_freed: u64,
+    /// Bytes allocated and not freed yet. In a measured scope, what the scope allocated minus
+    /// what it freed, or 0 if it freed more.
+    pub live_bytes: u64,
+    /// The most `live_bytes` has been.
+    pub peak_bytes: u64,
+}
+
+/// Wraps an allocator and keeps [`AllocStats`] for it.
+///
+/// Counting is a few relaxed atomic adds per call, so it's cheap enough to leave on as the
+/// global allocator:
+///
+/// ```
+/// use std::alloc::System;
+/// use rust_learning::allocator::TrackingAlloc;
+///
+/// #[global_allocator]
+/// static ALLOC: TrackingAlloc<System> = TrackingAlloc::new(System);
+///
+/// fn main() {
+///     let (_, stats) = ALLOC.measure(|| vec![0_u8; 64]);
+///     assert_eq!(stats.allocations, 1);
+///     assert_eq!(stats.bytes_allocated, 64);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TrackingAlloc<A = System> {
+    inner: A,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    reallocations: AtomicUsize,
+    bytes_allocated: AtomicUsize,
+    bytes_freed: AtomicUsize,
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    profiler: Option<&'static HeapProfiler>,
+}
+
+// What the current thread has done, for `measure`. These are const-initialized cells without
+// destructors, so touching them from inside the allocator never allocates.
+struct ThreadCounters {
+    allocations: Cell<u64>,
+    deallocations: Cell<u64>,
+    reallocations: Cell<u64>,
+    bytes_allocated: Cell<u64>,
+    bytes_freed: Cell<u64>,
+    // goes negative when the thread frees memory another thread allocated
+    live: Cell<i64>,
+    peak: Cell<i64>,
+}
+
+thread_local! {
+    static THREAD: ThreadCounters = const {
+        ThreadCounters {
+            allocations: Cell::new(0),
+            deallocations: Cell::new(0),
+            reallocations: Cell::new(0),
+            bytes_allocated: Cell::new(0),
+            bytes_freed: Cell::new(0),
+            live: Cell::new(0),
+            peak: Cell::new(0),
+        }
+    };
+}
+
+fn bump(cell: &Cell<u64>, by: u64) {
+    cell.set(cell.get() + by);
+}
+
+impl ThreadCounters {
+    fn grow(&self, by: usize) {
+        let live = self.live.get() + by as i64;
+        self.live.set(live);
+        self.peak.set(self.peak.get().max(live));
+    }
+
+    fn totals(&self) -> AllocStats {
+        AllocStats {
+            allocations: self.allocations.get(),
+            deallocations: self.deallocations.get(),
+            reallocations: self.reallocations.get(),
+            bytes_allocated: self.bytes_allocated.get(),
+            bytes_freed: self.bytes_freed.get(),
+            live_bytes: 0,
+            peak_bytes: 0,
+        }
+    }
+}
+
+impl<A> TrackingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            reallocations: AtomicUsize::new(0),
+            bytes_allocated: AtomicUsize::new(0),
+            bytes_freed: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            profiler: None,
+        }
+    }
+
+    /// Reports allocations to `profiler` too, which samples them while it's turned on.
+    pub const fn with_profiler(mut self, profiler: &'static HeapProfiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Totals across every thread since the allocator was created.
+    pub fn stats(&self) -> AllocStats {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+        AllocStats {
+            allocations: load(&self.allocations),
+            deallocations: load(&self.deallocations),
+            reallocations: load(&self.reallocations),
+            bytes_allocated: load(&self.bytes_allocated),
+            bytes_freed: load(&self.bytes_freed),
+            live_bytes: load(&self.live_bytes),
+            peak_bytes: load(&self.peak_bytes),
+        }
+    }
+
+    /// Starts peak tracking over from what's live now.
+    pub fn reset_peak(&self) {
+        self.peak_bytes
+            .store(self.live_bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Runs `f` and returns what it allocated on this thread. Scopes nest, and other threads'
+    /// allocations don't count, so an async test should use the current-thread runtime.
+    ///
+    /// The per-thread counts are shared by every `TrackingAlloc`, which only matters if you
+    /// have more than one.
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> (R, AllocStats) {
+        let (before, live_before, outer_peak) = THREAD.with(|thread| {
+            let live = thread.live.get();
+            let outer_peak = thread.peak.replace(live);
+            (thread.totals(), live, outer_peak)
+        });
+
+        let result = f();
+
+        THREAD.with(|thread| {
+            let after = thread.totals();
+            let live = thread.live.get();
+            let peak = thread.peak.replace(outer_peak.max(thread.peak.get()));
+            let stats = AllocStats {
+                allocations: after.allocations - before.allocations,
+                deallocations: after.deallocations - before.deallocations,
+                reallocations: after.reallocations - before.reallocations,
+                bytes_allocated: after.bytes_allocated - before.bytes_allocated,
+                bytes_freed: after.bytes_freed - before.bytes_freed,
+                live_bytes: (live - live_before).max(0) as u64,
+                peak_bytes: (peak - live_before).max(0) as u64,
+            };
+            (result, stats)
+        })
+    }
+
+    fn grow(&self, by: usize) {
+        let live = self.live_bytes.fetch_add(by, Ordering::Relaxed) + by;
+        self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+    }
+
+    fn record_alloc(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_allocated.fetch_add(size, Ordering::Relaxed);
+        self.grow(size);
+        let _ = THREAD.try_with(|thread| {
+            bump(&thread.allocations, 1);
+            bump(&thread.bytes_allocated, size as u64);
+            thread.grow(size);
+        });
+    }
+
+    fn record_dealloc(&self, size: usize) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_freed.fetch_add(size, Ordering::Relaxed);
+        self.live_bytes.fetch_sub(size, Ordering::Relaxed);
+        let _ = THREAD.try_with(|thread| {
+            bump(&thread.deallocations, 1);
+            bump(&thread.bytes_freed, size as u64);
+            thread.live.set(thread.live.get() - size as i64);
+        });
+    }
+
+    fn record_realloc(&self, old_size: usize, new_size: usize) {
+        self.reallocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_allocated.fetch_add(new_size, Ordering::Relaxed);
+        self.bytes_freed.fetch_add(old_size, Ordering::Relaxed);
+        self.live_bytes.fetch_sub(old_size, Ordering::Relaxed);
+        self.grow(new_size);
+        let _ = THREAD.try_with(|thread| {
+            bump(&thread.reallocations, 1);
+            bump(&thread.bytes_allocated, new_size as u64);
+            bump(&thread.bytes_freed, old_size as u64);
+            thread.live.set(thread.live.get() - old_size as i64);
+            thread.grow(new_size);
+        });
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.record_alloc(layout.size());
+            if let Some(profiler) = self.profiler {
+                profiler.allocated(ptr, layout.size());
+            }
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            self.record_alloc(layout.size());
+            if let Some(profiler) = self.profiler {
+                profiler.allocated(ptr, layout.size());
+            }
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(profiler) = self.profiler {
+            profiler.freed(ptr);
+        }
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // the old sample goes even if this fails, which only loses a sample
+        if let Some(profiler) = self.profiler {
+            profiler.freed(ptr);
+        }
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            self.record_realloc(layout.size(), new_size);
+            if let Some(profiler) = self.profiler {
+                profiler.allocated(new_ptr, new_size);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    // not the global allocator, so only the calls below are counted on it
+    #[test]
+    fn counts_calls_and_bytes() {
+        let alloc = TrackingAlloc::new(System);
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let a = alloc.alloc(small);
+            let b = alloc.alloc_zeroed(large);
+            let a = alloc.realloc(a, small, 64);
+            alloc.dealloc(b, large);
+            assert_eq!(
+                alloc.stats(),
+                AllocStats {
+                    allocations: 2,
+                    deallocations: 1,
+                    reallocations: 1,
+                    bytes_allocated: 16 + 100 + 64,
+                    bytes_freed: 100 + 16,
+                    live_bytes: 64,
+                    peak_bytes: 164,
+                }
+            );
+
+            alloc.reset_peak();
+            assert_eq!(alloc.stats().peak_bytes, 64);
+            alloc.dealloc(a, Layout::from_size_align(64, 8).unwrap());
+        }
+        assert_eq!(alloc.stats().live_bytes, 0);
+    }
+
+    #[test]
+    fn measures_the_current_thread_in_nested_scopes() {
+        let alloc = TrackingAlloc::new(System);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        let ((), outer) = alloc.measure(|| unsafe {
+            let kept = alloc.alloc(layout);
+            let ((), inner) = alloc.measure(|| {
+                let temporary = alloc.alloc(layout);
+                alloc.dealloc(temporary, layout);
+            });
+            assert_eq!(
+                inner,
+                AllocStats {
+                    allocations: 1,
+                    deallocations: 1,
+                    bytes_allocated: 32,
+                    bytes_freed: 32,
+                    live_bytes: 0,
+                    peak_bytes: 32,
+                    ..AllocStats::default()
+                }
+            );
+
+            // a scope that only frees
+            let ((), freeing) = alloc.measure(|| alloc.dealloc(kept, layout));
+            assert_eq!((freeing.live_bytes, freeing.peak_bytes), (0, 0));
+        });
+
+        assert_eq!((outer.allocations, outer.deallocations), (2, 2));
+        assert_eq!((outer.live_bytes, outer.peak_bytes), (0, 64));
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tokio::{sync::Mutex, time::Instant};
+
+use super::serve::Readiness;
+
+/// A dependency the service needs to do its job.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// Pings a sqlx pool with `SELECT 1`.
+pub struct SqlxCheck(pub SqlitePool);
+
+#[async_trait]
+impl HealthCheck for SqlxCheck {
+    async fn check(&self) -> Result<(), String> {
+        sqlx::query("SELECT 1")
+            .execute(&self.0)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Expects a 2xx from a downstream url.
+pub struct HttpCheck(pub String);
+
+#[async_trait]
+impl HealthCheck for HttpCheck {
+    async fn check(&self) -> Result<(), String> {
+        let res = isahc::get_async(&self.0).await.map_err(|e| e.to_string())?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("unexpected status {}", res.status()))
+        }
+    }
+}
+
+/// Wraps an async closure, for one-off checks.
+pub struct CheckFn<F>(pub F);
+
+#[async_trait]
+impl<F, Fut> HealthCheck for CheckFn<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), String>> + Send,
+{
+    async fn check(&self) -> Result<(), String> {
+        (self.0)().await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub status: Status,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+struct NamedCheck {
+    name: String,
+    check: Arc<dyn HealthCheck>,
+    timeout: Duration,
+}
+
+/// Mounts `/healthz`, `/readyz` and `/livez`.
+///
+/// - `/livez` is 200 as long as the process can answer at all.
+/// - `/healthz` runs the checks and reports on each of them.
+/// - `/readyz` is the same report, but also fails once [`Readiness`] flips during shutdown.
+pub struct Health {
+    checks: Vec<NamedCheck>,
+    readiness: Readiness,
+    cache_for: Duration,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            checks: Vec::new(),
+            readiness: Readiness::new(),
+            cache_for: Duration::ZERO,
+        }
+    }
+
+    pub fn check(
+        mut self,
+        name: &str,
+        check: impl HealthCheck + 'static,
+        timeout: Duration,
+    ) -> Self {
+        self.checks.push(NamedCheck {
+            name: name.to_owned(),
+            check: Arc::new(check),
+            timeout,
+        });
+        self
+    }
+
+    /// Shares the readiness flag with [`serve`](super::serve::serve), so `/readyz` fails
+    /// while draining.
+    pub fn readiness(mut self, readiness: Readiness) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    /// Reuses a report for this long, so frequent probes don't hammer the dependencies.
+    pub fn cache_for(mut self, ttl: Duration) -> Self {
+        self.cache_for = ttl;
+        self
+    }
+
+    pub fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        let state = Arc::new(HealthState {
+            checks: self.checks,
+            readiness: self.readiness,
+            cache_for: self.cache_for,
+            cached: Mutex::new(None),
+        });
+
+        Router::new()
+            .route("/livez", get(|| async { "ok" }))
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .with_state(state)
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct HealthState {
+    checks: Vec<NamedCheck>,
+    readiness: Readiness,
+    cache_for: Duration,
+    cached: Mutex<Option<(Instant, HealthReport)>>,
+}
+
+impl HealthState {
+    async fn report(&self) -> HealthReport {
+        // holding the lock while the checks run means concurrent probes share one run
+        let mut cached = self.cached.lock().await;
+        if let Some((at, report)) = &*cached
+            && at.elapsed() < self.cache_for
+        {
+            return report.clone();
+        }
+
+        let results = futures::future::join_all(self.checks.iter().map(run_check)).await;
+        let status = if results.iter().all(|(_, r)| r.status == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        let report = HealthReport {
+            status,
+            checks: results.into_iter().collect(),
+        };
+
+        *cached = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+async fn run_check(check: &NamedCheck) -> (String, CheckReport) {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(check.timeout, check.check.check()).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {:?}", check.timeout)),
+    };
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let report = match result {
+        Ok(()) => CheckReport {
+            status: Status::Up,
+            latency_ms,
+            error: None,
+        },
+        Err(error) => CheckReport {
+            status: Status::Down,
+            latency_ms,
+            error: Some(error),
+        },
+    };
+    (check.name.clone(), report)
+}
+
+fn respond(report: HealthReport) -> Response {
+    let status = match report.status {
+        Status::Up => StatusCode::OK,
+        Status::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report)).into_response()
+}
+
+async fn healthz(State(state): State<Arc<HealthState>>) -> Response {
+    respond(state.report().await)
+}
+
+async fn readyz(State(state): State<Arc<HealthState>>) -> Response {
+    let mut report = state.report().await;
+    if !state.readiness.is_ready() {
+        report.status = Status::Down;
+    }
+    respond(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    async fn get_json(app: Router, uri: &str) -> (StatusCode, Value) {
+        let res = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn reports_each_dependency() {
+        let pool = SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.path("/status");
+                then.status(200);
+            })
+            .await;
+
+        let app = Health::new()
+            .check("db", SqlxCheck(pool), Duration::from_secs(1))
+            .check(
+                "downstream",
+                HttpCheck(server.url("/status")),
+                Duration::from_secs(1),
+            )
+            .router();
+
+        let (status, report) = get_json(app, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "up");
+        assert_eq!(report["checks"]["db"]["status"], "up");
+        assert_eq!(report["checks"]["downstream"]["status"], "up");
+        assert!(report["checks"]["db"]["latency_ms"].is_f64());
+    }
+
+    #[tokio::test]
+    async fn failing_checks_fail_readiness() {
+        let app = Health::new()
+            .check("ok", CheckFn(|| async { Ok(()) }), Duration::from_secs(1))
+            .check(
+                "broken",
+                CheckFn(|| async { Err("connection refused".to_string()) }),
+                Duration::from_secs(1),
+            )
+            .router();
+
+        let (status, report) = get_json(app.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "down");
+        assert_eq!(report["checks"]["ok"]["status"], "up");
+        assert_eq!(
+            report["checks"]["broken"],
+            json!({
+                "status": "down",
+                "latency_ms": report["checks"]["broken"]["latency_ms"],
+                "error": "connection refused",
+            })
+        );
+
+        // liveness doesn't care about dependencies
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .uri("/livez")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_checks_time_out() {
+        let app = Health::new()
+            .check(
+                "slow",
+                CheckFn(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    Ok(())
+                }),
+                Duration::from_millis(100),
+            )
+            .router();
+
+        let (status, report) = get_json(app, "/healthz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["checks"]["slow"]["error"], "timed out after 100ms");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_reports() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let app = Health::new()
+            .check(
+                "counted",
+                CheckFn(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async { Ok(()) }
+                }),
+                Duration::from_secs(1),
+            )
+            .cache_for(Duration::from_secs(5))
+            .router();
+
+        get_json(app.clone(), "/healthz").await;
+        get_json(app.clone(), "/readyz").await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        get_json(app, "/healthz").await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn readiness_follows_shutdown() {
+        let readiness = Readiness::new();
+        let app = Health::new().readiness(readiness.clone()).router();
+
+        assert_eq!(get_json(app.clone(), "/readyz").await.0, StatusCode::OK);
+        readiness.set_ready(false);
+        assert_eq!(
+            get_json(app.clone(), "/readyz").await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // healthz is about dependencies, not about whether we're draining
+        assert_eq!(get_json(app, "/healthz").await.0, StatusCode::OK);
+    }
+}
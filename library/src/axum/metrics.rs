@@ -0,0 +1,557 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::{
+    Router,
+    extract::{MatchedPath, Request},
+    http::{Method, header},
+    response::Response,
+    routing::get,
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+// the same defaults the prometheus client libraries use, in seconds
+const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestLabels {
+    method: &'static str,
+    route: String,
+    status: &'static str,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    // cumulative counts, one per bucket
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<RequestLabels, Histogram>,
+    in_flight: BTreeMap<String, i64>,
+}
+
+/// Request rate, errors and duration for every route of a `Router`, in the Prometheus text
+/// format.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    buckets: Arc<[f64]>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    /// Uses custom histogram bucket bounds, in seconds. They're sorted and deduplicated, and
+    /// `+Inf` is always added, so they have to be finite.
+    pub fn with_buckets(buckets: &[f64]) -> Self {
+        assert!(
+            buckets.iter().all(|bound| bound.is_finite()),
+            "bucket bounds have to be finite, got {buckets:?}"
+        );
+        let mut buckets = buckets.to_vec();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        Self {
+            registry: Arc::default(),
+            buckets: buckets.into(),
+        }
+    }
+
+    pub fn layer(&self) -> MetricsLayer {
+        MetricsLayer {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Tracks how many requests are inside whatever it wraps. Put it just inside a
+    /// `ConcurrencyLimitLayer` to see how many permits are taken.
+    pub fn in_flight_layer(&self, name: &str) -> InFlightLayer {
+        self.registry
+            .lock()
+            .unwrap()
+            .in_flight
+            .entry(name.to_owned())
+            .or_default();
+        InFlightLayer {
+            metrics: self.clone(),
+            name: name.into(),
+        }
+    }
+
+    /// A router serving `GET /metrics`.
+    pub fn router<S: Clone + Send + Sync + 'static>(&self) -> Router<S> {
+        let metrics = self.clone();
+        Router::new().route(
+            "/metrics",
+            get(move || async move {
+                (
+                    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics.render(),
+                )
+            }),
+        )
+    }
+
+    fn observe(&self, labels: RequestLabels, seconds: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        let histogram = registry
+            .requests
+            .entry(labels)
+            .or_insert_with(|| Histogram {
+                buckets: vec![0; self.buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (bound, count) in self.buckets.iter().zip(&mut histogram.buckets) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    fn add_in_flight(&self, name: &str, delta: i64) {
+        *self
+            .registry
+            .lock()
+            .unwrap()
+            .in_flight
+            .entry(name.to_owned())
+            .or_default() += delta;
+    }
+
+    /// Renders everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (labels, histogram) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{{}}} {}",
+                labels.render(),
+                histogram.count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds HTTP request latency.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (labels, histogram) in &registry.requests {
+            let labels = labels.render();
+            for (bound, count) in self.buckets.iter().zip(&histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+
+        if !registry.in_flight.is_empty() {
+            out.push_str("# HELP http_requests_in_flight Requests currently being handled.\n");
+            out.push_str("# TYPE http_requests_in_flight gauge\n");
+            for (name, value) in &registry.in_flight {
+                let _ = writeln!(
+                    out,
+                    "http_requests_in_flight{{limiter=\"{}\"}} {value}",
+                    escape(name)
+                );
+            }
+        }
+
+        out
+    }
+}
+
+impl RequestLabels {
+    fn render(&self) -> String {
+        format!(
+            "method=\"{}\",route=\"{}\",status=\"{}\"",
+            self.method,
+            escape(&self.route),
+            self.status
+        )
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// any client can make up methods, so only the standard ones get a series of their own
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "other",
+    }
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Records every request against its route template, e.g. `/songs/{id}` rather than
+/// `/songs/42`, so the number of series stays bounded. Add it after the last `route`/`merge`
+/// call, so the fallback gets counted too.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S> Service<Request> for MetricsService<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let method = method_label(req.method());
+        // requests that didn't match any route all share one label instead of their raw path
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_owned())
+            .unwrap_or_else(|| "unmatched".to_owned());
+
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            // errors never reach the client as a response, so count them as server errors
+            let status = res.as_ref().map(|r| r.status().as_u16()).unwrap_or(500);
+            metrics.observe(
+                RequestLabels {
+                    method,
+                    route,
+                    status: status_class(status),
+                },
+                start.elapsed().as_secs_f64(),
+            );
+            res
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightLayer {
+    metrics: Metrics,
+    name: Arc<str>,
+}
+
+impl<S> Layer<S> for InFlightLayer {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            metrics: self.metrics.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightService<S> {
+    inner: S,
+    metrics: Metrics,
+    name: Arc<str>,
+}
+
+// decrements when the request finishes or is cancelled
+struct InFlightGuard {
+    metrics: Metrics,
+    name: Arc<str>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.metrics.add_in_flight(&self.name, -1);
+    }
+}
+
+impl<S, Req> Service<Req> for InFlightService<S>
+where
+    S: Service<Req>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.metrics.add_in_flight(&self.name, 1);
+        let guard = InFlightGuard {
+            metrics: self.metrics.clone(),
+            name: self.name.clone(),
+        };
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            drop(guard);
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::get};
+    use std::time::Duration;
+    use tokio::time::sleep;
+    use tower::{ServiceBuilder, ServiceExt, limit::ConcurrencyLimitLayer};
+
+    fn app(metrics: &Metrics) -> Router {
+        Router::new()
+            .route(
+                "/songs/{id}",
+                get(|| async {
+                    sleep(Duration::from_millis(20)).await;
+                    "song"
+                }),
+            )
+            .route(
+                "/broken",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .merge(metrics.router())
+            .layer(metrics.layer())
+    }
+
+    async fn hit(app: &Router, uri: &str) -> Response {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn scrape(app: &Router) -> String {
+        let res = hit(app, "/metrics").await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn labels_by_route_template() {
+        let metrics = Metrics::new();
+        let app = app(&metrics);
+
+        for id in 0..3 {
+            hit(&app, &format!("/songs/{id}")).await;
+        }
+        hit(&app, "/broken").await;
+        hit(&app, "/nope").await;
+
+        let text = scrape(&app).await;
+        assert!(
+            text.contains(
+                r#"http_requests_total{method="GET",route="/songs/{id}",status="2xx"} 3"#
+            ),
+            "{text}"
+        );
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="/broken",status="5xx"} 1"#)
+        );
+        assert!(text.contains(r#"route="unmatched",status="4xx"} 1"#));
+        // the raw paths never show up
+        assert!(!text.contains("/songs/0"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn records_duration_histograms() {
+        let metrics = Metrics::new();
+        let app = app(&metrics);
+
+        hit(&app, "/songs/1").await;
+
+        let text = scrape(&app).await;
+        let labels = r#"method="GET",route="/songs/{id}",status="2xx""#;
+        // 20ms lands in the 25ms bucket but not the 10ms one
+        assert!(text.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"0.01\"}} 0"
+        )));
+        assert!(text.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"0.025\"}} 1"
+        )));
+        assert!(text.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 1"
+        )));
+        assert!(text.contains(&format!(
+            "http_request_duration_seconds_count{{{labels}}} 1"
+        )));
+        assert!(text.contains("# TYPE http_request_duration_seconds histogram"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gauges_concurrency_limiter() {
+        let metrics = Metrics::new();
+        let svc = ServiceBuilder::new()
+            .layer(ConcurrencyLimitLayer::new(2))
+            .layer(metrics.in_flight_layer("songs"))
+            .service(Router::new().route(
+                "/hold",
+                get(|| async {
+                    sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            ));
+
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            let svc = svc.clone();
+            held.push(tokio::spawn(async move {
+                svc.oneshot(Request::builder().uri("/hold").body(Body::empty()).unwrap())
+                    .await
+            }));
+        }
+        tokio::time::advance(Duration::from_millis(1)).await;
+
+        // only two requests got a permit, the third is waiting outside
+        assert!(
+            metrics
+                .render()
+                .contains("http_requests_in_flight{limiter=\"songs\"} 2")
+        );
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        for h in held {
+            h.await.unwrap().unwrap();
+        }
+        assert!(
+            metrics
+                .render()
+                .contains("http_requests_in_flight{limiter=\"songs\"} 0")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn made_up_methods_share_a_label() {
+        let metrics = Metrics::new();
+        let app = app(&metrics);
+
+        for method in ["BREW", "WHEN", "PROPFIND"] {
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri("/songs/1")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let text = scrape(&app).await;
+        assert!(
+            text.contains(
+                r#"http_requests_total{method="other",route="/songs/{id}",status="4xx"} 3"#
+            ),
+            "{text}"
+        );
+        assert!(!text.contains("BREW"));
+    }
+
+    #[test]
+    fn buckets_are_sorted_and_deduplicated() {
+        let metrics = Metrics::with_buckets(&[1.0, 0.1, 1.0, 0.5]);
+        assert_eq!(&*metrics.buckets, [0.1, 0.5, 1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket bounds have to be finite")]
+    fn buckets_have_to_be_finite() {
+        Metrics::with_buckets(&[0.1, f64::NAN]);
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -1,3 +1,7 @@
+pub mod health;
+pub mod metrics;
+pub mod serve;
+
 #[cfg(test)]
 mod tests {
 
@@ -0,0 +1,449 @@
+use std::{
+    future::{Future, IntoFuture},
+    io,
+    net::SocketAddr,
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::Request,
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, watch},
+};
+use tower::{Layer, Service};
+
+/// Whether the server wants new traffic. Flipped to not ready as soon as shutdown starts, so
+/// load balancers stop routing to us before we stop accepting connections.
+#[derive(Debug, Clone)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.0.store(ready, Ordering::SeqCst);
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    pub addr: SocketAddr,
+    /// How long in-flight requests get to finish once we stop accepting.
+    pub drain_timeout: Duration,
+    /// How long to keep serving after readiness flips, before we stop accepting. Give load
+    /// balancers at least one probe interval to notice.
+    pub readiness_grace: Duration,
+    /// Where readiness is reported, if anywhere. A 503 once shutdown starts, otherwise the
+    /// router's own answer, like [`Health`](super::health::Health)'s report, or a bare 200 if
+    /// it has none.
+    pub readiness_path: Option<&'static str>,
+    pub readiness: Readiness,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            drain_timeout: Duration::from_secs(30),
+            readiness_grace: Duration::from_secs(5),
+            readiness_path: Some("/readyz"),
+            readiness: Readiness::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests that were in flight when shutdown started and finished in time.
+    pub drained: usize,
+    /// Requests that were still running at the drain deadline and got cut off with a 503.
+    pub aborted: usize,
+}
+
+/// Serves `router` on `config.addr` until SIGINT or SIGTERM, then drains.
+pub async fn serve(router: Router, config: ServeConfig) -> io::Result<ShutdownReport> {
+    let listener = TcpListener::bind(config.addr).await?;
+    serve_with_shutdown(listener, router, config, shutdown_signal()).await
+}
+
+/// Like [`serve`], but shuts down when `signal` completes instead.
+pub async fn serve_with_shutdown(
+    listener: TcpListener,
+    router: Router,
+    config: ServeConfig,
+    signal: impl Future<Output = ()>,
+) -> io::Result<ShutdownReport> {
+    let readiness = config.readiness.clone();
+    // a layer rather than a route, so a router that already has one doesn't clash with it
+    let router = match config.readiness_path {
+        Some(path) => {
+            let readiness = readiness.clone();
+            router.layer(middleware::from_fn(move |req: Request, next: Next| {
+                let readiness = readiness.clone();
+                async move {
+                    if req.uri().path() != path {
+                        return next.run(req).await;
+                    }
+                    if !readiness.is_ready() {
+                        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+                    }
+                    let res = next.run(req).await;
+                    if res.status() == StatusCode::NOT_FOUND {
+                        (StatusCode::OK, "ready").into_response()
+                    } else {
+                        res
+                    }
+                }
+            }))
+        }
+        None => router,
+    };
+
+    let in_flight = InFlight::default();
+    let router = router.layer(in_flight.clone());
+
+    let (stop_accepting, stopped) = oneshot::channel::<()>();
+    let mut server = pin!(
+        axum::serve(listener, router)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            })
+            .into_future()
+    );
+
+    // the server only finishes on its own if accepting fails
+    tokio::select! {
+        res = &mut server => return res.map(|()| ShutdownReport { drained: 0, aborted: 0 }),
+        () = signal => {}
+    }
+
+    readiness.set_ready(false);
+    tokio::select! {
+        res = &mut server => return res.map(|()| ShutdownReport { drained: 0, aborted: 0 }),
+        () = tokio::time::sleep(config.readiness_grace) => {}
+    }
+
+    let started_with = in_flight.count();
+    let _ = stop_accepting.send(());
+
+    match tokio::time::timeout(config.drain_timeout, &mut server).await {
+        Ok(res) => res.map(|()| ShutdownReport {
+            drained: started_with,
+            aborted: 0,
+        }),
+        Err(_) => {
+            // connections run on their own tasks, so dropping the server wouldn't stop them.
+            // instead every handler still running is told to give up.
+            let aborted = in_flight.abort();
+            let _ = tokio::time::timeout(ABORT_FLUSH_TIMEOUT, &mut server).await;
+            Ok(ShutdownReport {
+                drained: started_with.saturating_sub(aborted),
+                aborted,
+            })
+        }
+    }
+}
+
+/// Completes on ctrl-c, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+// how long aborted requests get to write out their 503s
+const ABORT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Counts requests whose handlers haven't finished yet, and can cut them all short.
+#[derive(Debug, Clone)]
+struct InFlight {
+    count: Arc<AtomicUsize>,
+    abort: Arc<watch::Sender<bool>>,
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self {
+            count: Arc::default(),
+            abort: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl InFlight {
+    fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Makes every running handler respond with a 503, returning how many there were.
+    fn abort(&self) -> usize {
+        let count = self.count();
+        self.abort.send_replace(true);
+        count
+    }
+}
+
+impl<S> Layer<S> for InFlight {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            count: self.count.clone(),
+            abort: self.abort.subscribe(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InFlightService<S> {
+    inner: S,
+    count: Arc<AtomicUsize>,
+    abort: watch::Receiver<bool>,
+}
+
+// decrements on drop, so requests whose futures get cancelled are counted as done too
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S, Req> Service<Req> for InFlightService<S>
+where
+    S: Service<Req, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.count.clone());
+        let mut abort = self.abort.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = tokio::select! {
+                res = fut => res,
+                _ = abort.wait_for(|aborted| *aborted) => {
+                    Ok((StatusCode::SERVICE_UNAVAILABLE, "server shutting down").into_response())
+                }
+            };
+            drop(guard);
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use pretty_assertions::assert_eq;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        task::JoinHandle,
+        time::sleep,
+    };
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    sleep(Duration::from_millis(200)).await;
+                    "finished"
+                }),
+            )
+            .route(
+                "/hang",
+                get(|| async {
+                    sleep(Duration::from_secs(60)).await;
+                    "never"
+                }),
+            )
+    }
+
+    // a tiny http/1.1 client so we can see exactly what happens to the connection
+    async fn get_raw(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        let _ = stream.read_to_string(&mut res).await;
+        res
+    }
+
+    async fn start(
+        config: ServeConfig,
+    ) -> (
+        SocketAddr,
+        oneshot::Sender<()>,
+        JoinHandle<io::Result<ShutdownReport>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_shutdown(listener, app(), config, async {
+            rx.await.ok();
+        }));
+        (addr, tx, server)
+    }
+
+    #[tokio::test]
+    async fn drains_in_flight_requests() {
+        let (addr, shutdown, server) = start(ServeConfig {
+            readiness_grace: Duration::ZERO,
+            ..Default::default()
+        })
+        .await;
+
+        let request = tokio::spawn(get_raw(addr, "/slow"));
+        sleep(Duration::from_millis(50)).await;
+        shutdown.send(()).unwrap();
+
+        let res = request.await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+        assert!(res.ends_with("finished"), "{res}");
+        assert_eq!(
+            server.await.unwrap().unwrap(),
+            ShutdownReport {
+                drained: 1,
+                aborted: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn aborts_requests_past_the_deadline() {
+        let (addr, shutdown, server) = start(ServeConfig {
+            drain_timeout: Duration::from_millis(100),
+            readiness_grace: Duration::ZERO,
+            ..Default::default()
+        })
+        .await;
+
+        let request = tokio::spawn(get_raw(addr, "/hang"));
+        sleep(Duration::from_millis(50)).await;
+        shutdown.send(()).unwrap();
+
+        assert_eq!(
+            server.await.unwrap().unwrap(),
+            ShutdownReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+        let res = request.await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 503"), "{res}");
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_before_accepting_stops() {
+        let readiness = Readiness::new();
+        let (addr, shutdown, server) = start(ServeConfig {
+            readiness_grace: Duration::from_millis(300),
+            readiness: readiness.clone(),
+            ..Default::default()
+        })
+        .await;
+
+        assert!(get_raw(addr, "/readyz").await.starts_with("HTTP/1.1 200"));
+
+        shutdown.send(()).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert!(!readiness.is_ready());
+
+        // still accepting during the grace period, but telling everyone to go away
+        let res = get_raw(addr, "/readyz").await;
+        assert!(res.starts_with("HTTP/1.1 503"), "{res}");
+
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shares_readiness_with_health() {
+        let readiness = Readiness::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = app().merge(
+            crate::axum::health::Health::new()
+                .readiness(readiness.clone())
+                .router(),
+        );
+        let (shutdown, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_shutdown(
+            listener,
+            router,
+            ServeConfig {
+                readiness_grace: Duration::from_millis(300),
+                readiness: readiness.clone(),
+                ..Default::default()
+            },
+            async {
+                rx.await.ok();
+            },
+        ));
+
+        // while ready, Health's report comes through
+        let res = get_raw(addr, "/readyz").await;
+        assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+        assert!(res.contains(r#""checks":{}"#), "{res}");
+
+        shutdown.send(()).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let res = get_raw(addr, "/readyz").await;
+        assert!(res.starts_with("HTTP/1.1 503"), "{res}");
+
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Manages the sqlite schema.
+//!
+//! ```text
+//! migrate [--database-url URL] [--source DIR] status
+//! migrate up [--to VERSION] [--dry-run]
+//! migrate down [--steps N] [--dry-run]
+//! migrate new <name>
+//! ```
+
+use std::{process::ExitCode, str::FromStr};
+
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use rust_learning::{
+    errors::CustomError,
+    sqlx::migrate::{Direction, Migrations, Step, new_migration},
+};
+use sqlx::{
+    Connection, SqliteConnection,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+};
+
+fn cli() -> Command {
+    Command::new("migrate")
+        .about("Applies, reverts and creates sqlite migrations")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("database-url")
+                .long("database-url")
+                .env("DATABASE_URL")
+                .default_value("sqlite:songs.db")
+                .global(true),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .default_value("migrations")
+                .help("Directory holding the migration files")
+                .global(true),
+        )
+        .subcommand(Command::new("status").about("Lists migrations and whether they're applied"))
+        .subcommand(
+            Command::new("up")
+                .about("Applies pending migrations")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(value_parser!(i64))
+                        .help("Stop after this version"),
+                )
+                .arg(dry_run()),
+        )
+        .subcommand(
+            Command::new("down")
+                .about("Reverts the most recent migrations")
+                .arg(
+                    Arg::new("steps")
+                        .long("steps")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1"),
+                )
+                .arg(dry_run()),
+        )
+        .subcommand(
+            Command::new("new")
+                .about("Creates a reversible up/down pair")
+                .arg(Arg::new("name").required(true)),
+        )
+}
+
+fn dry_run() -> Arg {
+    Arg::new("dry-run")
+        .long("dry-run")
+        .action(ArgAction::SetTrue)
+        .help("Print the SQL instead of running it")
+}
+
+// Only a real `up` or `down` creates the database or switches it to WAL. Everything else
+// opens it read-only, and a database that isn't there yet is an empty one in memory, with
+// every migration pending.
+async fn connect(matches: &ArgMatches, write: bool) -> Result<SqliteConnection, CustomError> {
+    let url = matches.get_one::<String>("database-url").unwrap();
+    let options = SqliteConnectOptions::from_str(url)?;
+    let options = if write {
+        options
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+    } else if options.get_filename().exists() {
+        options.read_only(true)
+    } else {
+        SqliteConnectOptions::from_str("sqlite::memory:")?
+    };
+    Ok(SqliteConnection::connect_with(&options).await?)
+}
+
+fn print_steps(steps: &[Step], dry_run: bool) {
+    if steps.is_empty() {
+        println!("nothing to do");
+    }
+    for step in steps {
+        let verb = match (step.direction, dry_run) {
+            (Direction::Up, false) => "applied",
+            (Direction::Down, false) => "reverted",
+            (Direction::Up, true) => "would apply",
+            (Direction::Down, true) => "would revert",
+        };
+        println!("{verb} {} {}", step.version, step.description);
+        if dry_run {
+            println!("{}\n", step.sql.trim_end());
+        }
+    }
+}
+
+async fn run(matches: ArgMatches) -> Result<(), CustomError> {
+    let source = matches.get_one::<String>("source").unwrap();
+
+    match matches.subcommand() {
+        Some(("new", sub)) => {
+            for path in new_migration(source, sub.get_one::<String>("name").unwrap())? {
+                println!("created {}", path.display());
+            }
+        }
+        Some(("status", _)) => {
+            let migrations = Migrations::load(source).await?;
+            for status in migrations
+                .status(&mut connect(&matches, false).await?)
+                .await?
+            {
+                println!("{status}");
+            }
+        }
+        Some(("up", sub)) => {
+            let migrations = Migrations::load(source).await?;
+            let dry_run = sub.get_flag("dry-run");
+            let to = sub.get_one::<i64>("to").copied();
+            let steps = migrations
+                .up(&mut connect(&matches, !dry_run).await?, to, dry_run)
+                .await?;
+            print_steps(&steps, dry_run);
+        }
+        Some(("down", sub)) => {
+            let migrations = Migrations::load(source).await?;
+            let dry_run = sub.get_flag("dry-run");
+            let steps = *sub.get_one::<usize>("steps").unwrap();
+            let steps = migrations
+                .down(&mut connect(&matches, !dry_run).await?, steps, dry_run)
+                .await?;
+            print_steps(&steps, dry_run);
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(cli().get_matches()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::sync::OnceCell;
+
+use super::{Cache, CacheStats};
+use crate::errors::CustomError;
+
+/// A cache in front of a slower source, such as the database or another service.
+///
+/// Misses load from the source and fill the cache. When several callers miss on the same key
+/// at once, only the first one loads; the rest wait for its value.
+pub struct LoadingCache<K, V, C> {
+    cache: C,
+    in_flight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+    loads: AtomicU64,
+    _values: PhantomData<fn() -> V>,
+}
+
+impl<K, V, C> LoadingCache<K, V, C>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    C: Cache<K, V>,
+{
+    pub fn new(cache: C) -> Self {
+        Self {
+            cache,
+            in_flight: Mutex::new(HashMap::new()),
+            loads: AtomicU64::new(0),
+            _values: PhantomData,
+        }
+    }
+
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// The cached value, or what `load` returns, which is then cached.
+    ///
+    /// A failed load isn't cached or shared: the callers that were waiting on it each try
+    /// their own `load` in turn.
+    pub async fn get_or_load<F, Fut>(&self, key: &K, load: F) -> Result<V, CustomError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, CustomError>>,
+    {
+        if let Some(value) = self.cache.get(key).await? {
+            return Ok(value);
+        }
+
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = cell
+            .get_or_try_init(|| async {
+                self.loads.fetch_add(1, Ordering::Relaxed);
+                let value = load().await?;
+                self.cache.insert(key.clone(), value.clone()).await?;
+                Ok(value)
+            })
+            .await
+            .cloned();
+
+        // later misses should load afresh rather than reuse this value forever
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            in_flight.remove(key);
+        }
+        result
+    }
+
+    /// Writes to the source with `store`, then caches `value` if that worked.
+    pub async fn write_through<F, Fut>(&self, key: K, value: V, store: F) -> Result<(), CustomError>
+    where
+        F: FnOnce(K, V) -> Fut,
+        Fut: Future<Output = Result<(), CustomError>>,
+    {
+        store(key.clone(), value.clone()).await?;
+        self.cache.insert(key, value).await
+    }
+
+    /// Drops `key` from the cache, so the next read loads it again.
+    pub async fn invalidate(&self, key: &K) -> Result<(), CustomError> {
+        self.cache.remove(key).await
+    }
+
+    /// The underlying cache's stats, with the loads made through this one.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            loads: self.loads.load(Ordering::Relaxed),
+            ..self.cache.stats()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::MemoryCache, errors::CustomErrorKind};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn cache() -> LoadingCache<&'static str, u32, MemoryCache<&'static str, u32>> {
+        LoadingCache::new(MemoryCache::new(10, Duration::from_secs(60)))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_concurrent_misses() {
+        let cache = cache();
+        let load = || async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(42)
+        };
+
+        let values =
+            futures::future::join_all((0..10).map(|_| cache.get_or_load(&"answer", load))).await;
+        assert!(values.into_iter().all(|value| value.unwrap() == 42));
+        assert_eq!(
+            cache
+                .get_or_load(&"answer", || async { panic!("cached") })
+                .await
+                .unwrap(),
+            42
+        );
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.loads), (1, 10, 1));
+        assert_eq!(stats.hit_ratio(), 1.0 / 11.0);
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_failed_loads() {
+        let cache = cache();
+        let err = cache
+            .get_or_load(&"flaky", || async {
+                Err(CustomError::new(CustomErrorKind::TimedOut, "source down"))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), CustomErrorKind::TimedOut);
+
+        assert_eq!(
+            cache
+                .get_or_load(&"flaky", || async { Ok(1) })
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(cache.stats().loads, 2);
+    }
+
+    #[tokio::test]
+    async fn writes_through_to_the_source() {
+        let cache = cache();
+        let source = Mutex::new(HashMap::new());
+        let store = |key, value| {
+            source.lock().unwrap().insert(key, value);
+            async { Ok(()) }
+        };
+
+        cache.write_through("a", 1, store).await.unwrap();
+        assert_eq!(source.lock().unwrap().get("a"), Some(&1));
+        assert_eq!(
+            cache
+                .get_or_load(&"a", || async { panic!("cached") })
+                .await
+                .unwrap(),
+            1
+        );
+
+        // nothing is cached if the source refused it
+        let err = cache
+            .write_through("b", 2, |_, _| async {
+                Err(CustomError::new(
+                    CustomErrorKind::InvalidArgument,
+                    "rejected",
+                ))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), CustomErrorKind::InvalidArgument);
+        assert_eq!(cache.cache().get(&"b"), None);
+
+        cache.invalidate(&"a").await.unwrap();
+        assert_eq!(
+            cache.get_or_load(&"a", || async { Ok(10) }).await.unwrap(),
+            10
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Mutex, atomic::Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use super::{Cache, CacheStats, Counters};
+use crate::errors::CustomError;
+
+/// An in-process cache holding at most `capacity` entries. When it's full, the least recently
+/// used entry makes room.
+#[derive(Debug)]
+pub struct MemoryCache<K, V> {
+    lru: Mutex<Lru<K, V>>,
+    capacity: usize,
+    ttl: Duration,
+    counters: Counters,
+}
+
+#[derive(Debug)]
+struct Lru<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // last use -> key, oldest first
+    order: BTreeMap<u64, K>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    used: u64,
+}
+
+impl<K, V> MemoryCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            lru: Mutex::new(Lru {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: 0,
+            }),
+            capacity: capacity.max(1),
+            ttl,
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut lru = self.lru.lock().unwrap();
+        let lru = &mut *lru;
+        let found = match lru.entries.get_mut(key) {
+            Some(entry) if entry.expires_at <= Instant::now() => {
+                lru.order.remove(&entry.used);
+                lru.entries.remove(key);
+                None
+            }
+            Some(entry) => {
+                lru.order.remove(&entry.used);
+                lru.clock += 1;
+                entry.used = lru.clock;
+                lru.order.insert(lru.clock, key.clone());
+                Some(entry.value.clone())
+            }
+            None => None,
+        };
+        self.counters.record(&found);
+        found
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.clock += 1;
+        let entry = Entry {
+            value,
+            expires_at: Instant::now() + self.ttl,
+            used: lru.clock,
+        };
+        lru.order.insert(entry.used, key.clone());
+        if let Some(old) = lru.entries.insert(key, entry) {
+            lru.order.remove(&old.used);
+        }
+        while lru.entries.len() > self.capacity {
+            let (_, oldest) = lru.order.pop_first().expect("order tracks every entry");
+            lru.entries.remove(&oldest);
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn remove(&self, key: &K) {
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(entry) = lru.entries.remove(key) {
+            lru.order.remove(&entry.used);
+        }
+    }
+
+    /// Entries held, including expired ones that haven't been looked up or evicted yet.
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for MemoryCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    async fn get(&self, key: &K) -> Result<Option<V>, CustomError> {
+        Ok(MemoryCache::get(self, key))
+    }
+
+    async fn insert(&self, key: K, value: V) -> Result<(), CustomError> {
+        MemoryCache::insert(self, key, value);
+        Ok(())
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), CustomError> {
+        MemoryCache::remove(self, key);
+        Ok(())
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let cache = MemoryCache::new(2, Duration::from_secs(60));
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // reading `a` makes `b` the oldest
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("c", 3);
+
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        // replacing doesn't evict
+        cache.insert("c", 30);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"c"), Some(30));
+
+        cache.remove(&"a");
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(
+            Cache::stats(&cache),
+            CacheStats {
+                hits: 4,
+                misses: 2,
+                evictions: 1,
+                loads: 0,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_after_the_ttl() {
+        let cache = MemoryCache::new(10, Duration::from_secs(60));
+        cache.insert("a", 1);
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("b", 2);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,80 @@
+//! Key-value caches behind one [`Cache`] trait: an in-process LRU ([`MemoryCache`]), a
+//! persistent sqlite table ([`SqliteCache`]), [`Tiered`] to put one in front of the other, and
+//! [`LoadingCache`] for read-through and write-through access to whatever they're caching.
+
+mod loading;
+mod memory;
+mod sqlite;
+mod tiered;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+
+use crate::errors::CustomError;
+
+pub use loading::LoadingCache;
+pub use memory::MemoryCache;
+pub use sqlite::SqliteCache;
+pub use tiered::Tiered;
+
+#[async_trait]
+pub trait Cache<K, V>: Send + Sync {
+    /// The value under `key`, unless it's missing or has expired.
+    async fn get(&self, key: &K) -> Result<Option<V>, CustomError>;
+    /// Stores `value`, replacing what was under `key`. It expires after the cache's TTL.
+    async fn insert(&self, key: K, value: V) -> Result<(), CustomError>;
+    async fn remove(&self, key: &K) -> Result<(), CustomError>;
+    fn stats(&self) -> CacheStats;
+}
+
+/// Counts since the cache was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room, not counting expired ones.
+    pub evictions: u64,
+    /// Misses that went to the source, for a [`LoadingCache`]. Concurrent misses for the same
+    /// key count once.
+    pub loads: u64,
+}
+
+impl CacheStats {
+    /// Hits over lookups, 0 before the first lookup.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Counters {
+    fn record<V>(&self, found: &Option<V>) {
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            loads: 0,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    marker::PhantomData,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{Serialize, de::DeserializeOwned};
+use sqlx::SqlitePool;
+
+use super::{Cache, CacheStats, Counters};
+use crate::errors::{CustomError, CustomErrorKind};
+
+/// A cache in the `cache_entries` table, so it survives restarts and is shared by every
+/// process on the database. Keys and values are stored as JSON.
+///
+/// Expired rows are skipped on read but stay in the table until [`SqliteCache::purge_expired`].
+#[derive(Debug)]
+pub struct SqliteCache<K, V> {
+    pool: SqlitePool,
+    namespace: String,
+    ttl: Duration,
+    counters: Counters,
+    _entries: PhantomData<fn() -> (K, V)>,
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, CustomError> {
+    serde_json::to_string(value).map_err(|e| {
+        CustomError::new(
+            CustomErrorKind::InvalidArgument,
+            "unserializable cache entry",
+        )
+        .with_source(e)
+    })
+}
+
+impl<K, V> SqliteCache<K, V> {
+    /// Caches sharing a pool are kept apart by `namespace`.
+    pub fn new(pool: SqlitePool, namespace: impl Into<String>, ttl: Duration) -> Self {
+        Self {
+            pool,
+            namespace: namespace.into(),
+            ttl,
+            counters: Counters::default(),
+            _entries: PhantomData,
+        }
+    }
+
+    /// Deletes this namespace's expired rows and returns how many went.
+    pub async fn purge_expired(&self) -> Result<u64, CustomError> {
+        let now = millis(SystemTime::now());
+        let result = sqlx::query!(
+            "DELETE FROM cache_entries WHERE namespace = ? AND expires_at <= ?",
+            self.namespace,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for SqliteCache<K, V>
+where
+    K: Serialize + Send + Sync,
+    V: Serialize + DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &K) -> Result<Option<V>, CustomError> {
+        let key = to_json(key)?;
+        let now = millis(SystemTime::now());
+        let value = sqlx::query_scalar!(
+            "SELECT value FROM cache_entries WHERE namespace = ? AND key = ? AND expires_at > ?",
+            self.namespace,
+            key,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let found = value
+            .map(|value| {
+                serde_json::from_str(&value).map_err(|e| {
+                    CustomError::new(CustomErrorKind::InvalidData, "undecodable cache entry")
+                        .with_source(e)
+                })
+            })
+            .transpose()?;
+        self.counters.record(&found);
+        Ok(found)
+    }
+
+    async fn insert(&self, key: K, value: V) -> Result<(), CustomError> {
+        let key = to_json(&key)?;
+        let value = to_json(&value)?;
+        let expires_at = millis(SystemTime::now() + self.ttl);
+        sqlx::query!(
+            "INSERT OR REPLACE INTO cache_entries (namespace, key, value, expires_at)
+            VALUES (?, ?, ?, ?)",
+            self.namespace,
+            key,
+            value,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), CustomError> {
+        let key = to_json(key)?;
+        sqlx::query!(
+            "DELETE FROM cache_entries WHERE namespace = ? AND key = ?",
+            self.namespace,
+            key
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlx::repository::Song;
+    use pretty_assertions::assert_eq;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn lemon() -> Song {
+        Song {
+            id: 1,
+            artist: "Kenshi Yonezu".to_owned(),
+            title: "Lemon".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_values_per_namespace() {
+        let pool = pool().await;
+        let songs = SqliteCache::<i64, Song>::new(pool.clone(), "songs", Duration::from_secs(60));
+        let titles = SqliteCache::<i64, String>::new(pool, "titles", Duration::from_secs(60));
+
+        songs.insert(1, lemon()).await.unwrap();
+        titles.insert(1, "Lemon".to_owned()).await.unwrap();
+        assert_eq!(songs.get(&1).await.unwrap(), Some(lemon()));
+        assert_eq!(titles.get(&1).await.unwrap().as_deref(), Some("Lemon"));
+
+        songs.remove(&1).await.unwrap();
+        assert_eq!(songs.get(&1).await.unwrap(), None);
+        assert_eq!(titles.get(&1).await.unwrap().as_deref(), Some("Lemon"));
+
+        let stats = songs.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn skips_and_purges_expired_rows() {
+        let pool = pool().await;
+        let expired = SqliteCache::<&str, u32>::new(pool.clone(), "expired", Duration::ZERO);
+        let fresh = SqliteCache::<&str, u32>::new(pool, "fresh", Duration::from_secs(60));
+        expired.insert("a", 1).await.unwrap();
+        expired.insert("b", 2).await.unwrap();
+        fresh.insert("a", 1).await.unwrap();
+
+        assert_eq!(expired.get(&"a").await.unwrap(), None);
+        assert_eq!(expired.purge_expired().await.unwrap(), 2);
+        assert_eq!(fresh.purge_expired().await.unwrap(), 0);
+        assert_eq!(fresh.get(&"a").await.unwrap(), Some(1));
+    }
+}
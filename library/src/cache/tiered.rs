@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+
+use super::{Cache, CacheStats, Counters};
+use crate::errors::CustomError;
+
+/// Looks in `near` first, then `far`, copying what `far` had into `near`. Writes and removals
+/// go to both.
+///
+/// Typically a [`MemoryCache`](super::MemoryCache) in front of a
+/// [`SqliteCache`](super::SqliteCache), so a restarted process starts warm.
+#[derive(Debug)]
+pub struct Tiered<N, F> {
+    near: N,
+    far: F,
+    counters: Counters,
+}
+
+impl<N, F> Tiered<N, F> {
+    pub fn new(near: N, far: F) -> Self {
+        Self {
+            near,
+            far,
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn near(&self) -> &N {
+        &self.near
+    }
+
+    pub fn far(&self) -> &F {
+        &self.far
+    }
+}
+
+#[async_trait]
+impl<K, V, N, F> Cache<K, V> for Tiered<N, F>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    N: Cache<K, V>,
+    F: Cache<K, V>,
+{
+    async fn get(&self, key: &K) -> Result<Option<V>, CustomError> {
+        let mut found = self.near.get(key).await?;
+        if found.is_none() {
+            found = self.far.get(key).await?;
+            if let Some(value) = &found {
+                self.near.insert(key.clone(), value.clone()).await?;
+            }
+        }
+        self.counters.record(&found);
+        Ok(found)
+    }
+
+    async fn insert(&self, key: K, value: V) -> Result<(), CustomError> {
+        self.far.insert(key.clone(), value.clone()).await?;
+        self.near.insert(key, value).await
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), CustomError> {
+        self.far.remove(key).await?;
+        self.near.remove(key).await
+    }
+
+    /// Hits in either tier. Each tier keeps its own stats too.
+    fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCache;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn fills_the_near_tier_from_the_far_one() {
+        let cache = Tiered::new(
+            MemoryCache::new(10, Duration::from_secs(60)),
+            MemoryCache::new(100, Duration::from_secs(3600)),
+        );
+        cache.far().insert("a", 1);
+
+        assert_eq!(cache.near().get(&"a"), None);
+        assert_eq!(Cache::get(&cache, &"a").await.unwrap(), Some(1));
+        assert_eq!(cache.near().get(&"a"), Some(1));
+
+        Cache::insert(&cache, "b", 2).await.unwrap();
+        assert_eq!(
+            (cache.near().get(&"b"), cache.far().get(&"b")),
+            (Some(2), Some(2))
+        );
+        Cache::remove(&cache, &"b").await.unwrap();
+        assert_eq!(
+            (cache.near().get(&"b"), cache.far().get(&"b")),
+            (None, None)
+        );
+
+        assert_eq!(Cache::get(&cache, &"c").await.unwrap(), None);
+        let stats = Cache::stats(&cache);
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+}
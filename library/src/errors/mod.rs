@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{collections::TryReserveError, fmt};
 
 use thiserror::Error;
 
@@ -15,6 +15,20 @@ pub enum CustomErrorKind {
     Other,
 }
 
+impl CustomErrorKind {
+    /// Whether the same operation could succeed if tried again later. Bad input, missing
+    /// things and denied permissions won't fix themselves.
+    pub fn is_retryable(self) -> bool {
+        !matches!(
+            self,
+            CustomErrorKind::NotFound
+                | CustomErrorKind::PermissionDenied
+                | CustomErrorKind::InvalidArgument
+                | CustomErrorKind::InvalidData
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub struct CustomError {
     kind: CustomErrorKind,
@@ -30,6 +44,53 @@ impl CustomError {
             message: message.into(),
         }
     }
+
+    pub fn with_source(mut self, source: impl Into<anyhow::Error>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn kind(&self) -> CustomErrorKind {
+        self.kind
+    }
+}
+
+// sqlite's primary result codes, extended codes keep these in their low byte
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+impl From<sqlx::Error> for CustomError {
+    fn from(error: sqlx::Error) -> Self {
+        let kind = match &error {
+            sqlx::Error::RowNotFound => CustomErrorKind::NotFound,
+            sqlx::Error::PoolTimedOut => CustomErrorKind::TimedOut,
+            sqlx::Error::ColumnNotFound(_)
+            | sqlx::Error::ColumnDecode { .. }
+            | sqlx::Error::Decode(_)
+            | sqlx::Error::TypeNotFound { .. }
+            | sqlx::Error::Protocol(_) => CustomErrorKind::InvalidData,
+            sqlx::Error::Database(db) => match db.kind() {
+                sqlx::error::ErrorKind::UniqueViolation
+                | sqlx::error::ErrorKind::ForeignKeyViolation
+                | sqlx::error::ErrorKind::NotNullViolation
+                | sqlx::error::ErrorKind::CheckViolation => CustomErrorKind::InvalidArgument,
+                _ => match db.code().and_then(|code| code.parse::<i32>().ok()) {
+                    Some(code) if matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED) => {
+                        CustomErrorKind::ResourceBusy
+                    }
+                    _ => CustomErrorKind::Other,
+                },
+            },
+            _ => CustomErrorKind::Other,
+        };
+        CustomError::new(kind, "database error").with_source(error)
+    }
+}
+
+impl From<TryReserveError> for CustomError {
+    fn from(error: TryReserveError) -> Self {
+        CustomError::new(CustomErrorKind::OutOfMemory, "allocation failed").with_source(error)
+    }
 }
 
 impl fmt::Display for CustomError {
@@ -85,6 +146,34 @@ mod tests {
         assert!(simulate_operation(false, CustomErrorKind::Other).is_ok());
     }
 
+    #[test]
+    fn sqlx_errors() {
+        let error = CustomError::from(sqlx::Error::RowNotFound);
+        assert_eq!(error.kind(), CustomErrorKind::NotFound);
+        assert!(error.source().is_some());
+
+        assert_eq!(
+            CustomError::from(sqlx::Error::PoolTimedOut).kind(),
+            CustomErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn failed_reservations_are_out_of_memory() {
+        let error = CustomError::from(Vec::<u8>::new().try_reserve(usize::MAX).unwrap_err());
+        assert_eq!(error.kind(), CustomErrorKind::OutOfMemory);
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn retryable_kinds() {
+        assert!(CustomErrorKind::ResourceBusy.is_retryable());
+        assert!(CustomErrorKind::TimedOut.is_retryable());
+        assert!(CustomErrorKind::Other.is_retryable());
+        assert!(!CustomErrorKind::InvalidData.is_retryable());
+        assert!(!CustomErrorKind::NotFound.is_retryable());
+    }
+
     // we can take a Box of dyn Error and turn it back into the concrete type by downcasting
     #[test]
     fn downcasting() {
@@ -1,6 +1,8 @@
+pub mod allocator;
 pub mod anyhow;
 pub mod async_trait;
 pub mod axum;
+pub mod cache;
 pub mod ctor;
 pub mod delegate;
 pub mod derive_more;
@@ -0,0 +1,371 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Mutex as StdMutex, PoisonError},
+};
+
+use parking_lot::{Mutex, MutexGuard};
+
+/// A [`parking_lot::Mutex`] that checks, in debug builds, that locks are always taken in the
+/// same order.
+///
+/// Every mutex belongs to a class, a name shared by all the mutexes that play the same part,
+/// like `"playlist"`. Taking a lock while holding others records "held before taken" for each
+/// pair of classes in a graph shared by the whole process. The first time a lock is taken
+/// against an order seen before, even on another thread and even if the two never actually
+/// collided, that's a [`LockOrderViolation`]: it panics, or goes to the handler set with
+/// [`on_lock_order_violation`].
+///
+/// In release builds it's a plain mutex with a name.
+///
+/// Here the second pair of locks is taken the other way round from the first, so a debug build
+/// panics on `songs.lock()` at the end while a release build runs to completion:
+///
+/// ```no_run
+/// use rust_learning::parking_lot::OrderedMutex;
+///
+/// let songs = OrderedMutex::new("songs", ());
+/// let plays = OrderedMutex::new("plays", ());
+///
+/// {
+///     let _songs = songs.lock();
+///     let _plays = plays.lock();
+/// }
+/// let _plays = plays.lock();
+/// let _songs = songs.lock();
+/// ```
+#[derive(Debug)]
+pub struct OrderedMutex<T> {
+    class: &'static str,
+    inner: Mutex<T>,
+}
+
+/// Unlocks and forgets the class when dropped.
+#[derive(Debug)]
+pub struct OrderedMutexGuard<'a, T> {
+    class: &'static str,
+    inner: MutexGuard<'a, T>,
+}
+
+/// A lock taken against the order recorded before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOrderViolation {
+    /// The class being locked.
+    pub acquiring: &'static str,
+    /// The class held while locking it, the wrong way round.
+    pub held: &'static str,
+    /// How `acquiring` was seen to come before `held`: a chain of "held before taken" from
+    /// `acquiring` to `held`.
+    pub order: Vec<&'static str>,
+}
+
+impl fmt::Display for LockOrderViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.acquiring == self.held {
+            return write!(f, "locking {:?} while already holding it", self.acquiring);
+        }
+        write!(
+            f,
+            "locking {:?} while holding {:?}, but they were locked in the order {}",
+            self.acquiring,
+            self.held,
+            self.order.join(" -> ")
+        )
+    }
+}
+
+impl std::error::Error for LockOrderViolation {}
+
+// "held before taken" edges between classes
+static GRAPH: StdMutex<BTreeMap<&'static str, BTreeSet<&'static str>>> =
+    StdMutex::new(BTreeMap::new());
+static HANDLER: StdMutex<Option<fn(&LockOrderViolation)>> = StdMutex::new(None);
+
+thread_local! {
+    // classes this thread holds, in the order it locked them
+    static HELD: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sends violations to `handler` instead of panicking, e.g. to log them from a service.
+/// `None` goes back to panicking.
+pub fn on_lock_order_violation(handler: Option<fn(&LockOrderViolation)>) {
+    *HANDLER.lock().unwrap_or_else(PoisonError::into_inner) = handler;
+}
+
+// a path of edges from `from` to `to`, both ends included
+fn path(
+    graph: &BTreeMap<&'static str, BTreeSet<&'static str>>,
+    from: &'static str,
+    to: &'static str,
+) -> Option<Vec<&'static str>> {
+    let mut came_from = BTreeMap::new();
+    let mut queue = vec![from];
+    while let Some(class) = queue.pop() {
+        if class == to {
+            let mut path = vec![to];
+            while let Some(&previous) = came_from.get(path.last().unwrap()) {
+                path.push(previous);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &next in graph.get(class).into_iter().flatten() {
+            if next != from && !came_from.contains_key(next) {
+                came_from.insert(next, class);
+                queue.push(next);
+            }
+        }
+    }
+    None
+}
+
+// Records taking `class` with everything this thread holds, and returns the first inversion.
+// Edges that would close a cycle aren't added, so each inversion is reported only as often as
+// it's repeated.
+fn record(class: &'static str) -> Option<LockOrderViolation> {
+    let held = HELD.with_borrow(|held| held.clone());
+    let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut violation = None;
+    for held in held {
+        if held == class {
+            violation.get_or_insert(LockOrderViolation {
+                acquiring: class,
+                held,
+                order: vec![class],
+            });
+            continue;
+        }
+        if graph.get(held).is_some_and(|after| after.contains(class)) {
+            continue;
+        }
+        match path(&graph, class, held) {
+            Some(order) => {
+                violation.get_or_insert(LockOrderViolation {
+                    acquiring: class,
+                    held,
+                    order,
+                });
+            }
+            None => {
+                graph.entry(held).or_default().insert(class);
+            }
+        }
+    }
+    violation
+}
+
+fn report(violation: LockOrderViolation) {
+    let handler = *HANDLER.lock().unwrap_or_else(PoisonError::into_inner);
+    match handler {
+        Some(handler) => handler(&violation),
+        None => panic!("lock order violation: {violation}"),
+    }
+}
+
+impl<T> OrderedMutex<T> {
+    pub const fn new(class: &'static str, value: T) -> Self {
+        Self {
+            class,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn class(&self) -> &'static str {
+        self.class
+    }
+
+    /// Checks the order against what's held, then blocks until it gets the lock.
+    pub fn lock(&self) -> OrderedMutexGuard<'_, T> {
+        if cfg!(debug_assertions)
+            && let Some(violation) = record(self.class)
+        {
+            report(violation);
+        }
+        self.guard(self.inner.lock())
+    }
+
+    /// Doesn't check the order, since it can't wait in a cycle, but what it gets counts as
+    /// held for locks taken after.
+    pub fn try_lock(&self) -> Option<OrderedMutexGuard<'_, T>> {
+        self.inner.try_lock().map(|inner| self.guard(inner))
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    fn guard<'a>(&self, inner: MutexGuard<'a, T>) -> OrderedMutexGuard<'a, T> {
+        if cfg!(debug_assertions) {
+            HELD.with_borrow_mut(|held| held.push(self.class));
+        }
+        OrderedMutexGuard {
+            class: self.class,
+            inner,
+        }
+    }
+}
+
+impl<T> Deref for OrderedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for OrderedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for OrderedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) {
+            // guards don't have to be dropped in the order they were taken
+            let _ = HELD.try_with(|held| {
+                let mut held = held.borrow_mut();
+                if let Some(at) = held.iter().rposition(|&class| class == self.class) {
+                    held.remove(at);
+                }
+            });
+        }
+    }
+}
+
+// the order is only checked in debug builds
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{panic, sync::Arc, thread};
+
+    // the graph is shared by every test, so each one uses its own class names
+    fn violation(f: impl FnOnce()) -> String {
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(f)).expect_err("no violation");
+        payload.downcast::<String>().map(|s| *s).unwrap_or_default()
+    }
+
+    #[test]
+    fn consistent_order_is_fine() {
+        let a = OrderedMutex::new("consistent-a", 0);
+        let b = OrderedMutex::new("consistent-b", 0);
+        let c = OrderedMutex::new("consistent-c", 0);
+
+        for _ in 0..3 {
+            let mut a = a.lock();
+            let mut b = b.lock();
+            *a += 1;
+            *b += 1;
+            drop(a);
+            // b is still held, and b before c is new but not a cycle
+            *c.lock() += 1;
+        }
+        {
+            // a before c follows from a before b before c
+            let _a = a.lock();
+            let _c = c.lock();
+        }
+        assert_eq!(*b.lock(), 3);
+    }
+
+    #[test]
+    fn inversion_on_another_thread_is_caught() {
+        let songs = Arc::new(OrderedMutex::new("inversion-songs", ()));
+        let plays = Arc::new(OrderedMutex::new("inversion-plays", ()));
+
+        let (songs2, plays2) = (songs.clone(), plays.clone());
+        thread::spawn(move || {
+            let _songs = songs2.lock();
+            let _plays = plays2.lock();
+        })
+        .join()
+        .unwrap();
+
+        let message = violation(move || {
+            let _plays = plays.lock();
+            let _songs = songs.lock();
+        });
+        assert_eq!(
+            message,
+            "lock order violation: locking \"inversion-songs\" while holding \"inversion-plays\", \
+             but they were locked in the order inversion-songs -> inversion-plays"
+        );
+    }
+
+    #[test]
+    fn finds_inversions_through_other_classes() {
+        let a = OrderedMutex::new("transitive-a", ());
+        let b = OrderedMutex::new("transitive-b", ());
+        let c = OrderedMutex::new("transitive-c", ());
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        {
+            let _b = b.lock();
+            let _c = c.lock();
+        }
+
+        let message = violation(|| {
+            let _c = c.lock();
+            let _a = a.lock();
+        });
+        assert!(
+            message.ends_with("transitive-a -> transitive-b -> transitive-c"),
+            "{message}"
+        );
+
+        // the guards were released as the panic unwound
+        HELD.with_borrow(|held| assert!(held.is_empty()));
+    }
+
+    #[test]
+    fn same_class_twice_is_a_violation() {
+        let first = OrderedMutex::new("nested-row", ());
+        let second = OrderedMutex::new("nested-row", ());
+        let message = violation(|| {
+            let _first = first.lock();
+            let _second = second.lock();
+        });
+        assert!(message.ends_with("while already holding it"), "{message}");
+    }
+
+    #[test]
+    fn try_lock_counts_as_held() {
+        let a = OrderedMutex::new("try-a", ());
+        let b = OrderedMutex::new("try-b", ());
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        let message = violation(|| {
+            let _b = b.try_lock().unwrap();
+            let _a = a.lock();
+        });
+        assert!(message.contains("while holding \"try-b\""), "{message}");
+    }
+}
+
+#[cfg(all(test, not(debug_assertions)))]
+mod release_tests {
+    use super::*;
+
+    #[test]
+    fn release_builds_dont_check_the_order() {
+        let a = OrderedMutex::new("release-a", 0);
+        let b = OrderedMutex::new("release-b", 0);
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        let _b = b.lock();
+        let _a = a.lock();
+    }
+}
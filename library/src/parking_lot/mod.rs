@@ -1,10 +1,26 @@
-#[cfg(test)]
+mod lock_order;
+#[cfg(feature = "deadlock_detection")]
+mod watchdog;
+
+pub use lock_order::{
+    LockOrderViolation, OrderedMutex, OrderedMutexGuard, on_lock_order_violation,
+};
+#[cfg(feature = "deadlock_detection")]
+pub use watchdog::{
+    DeadlockReport, DeadlockWatchdog, DeadlockedThread, OnDeadlock, WatchdogBuilder,
+};
+
+#[cfg(all(test, feature = "deadlock_detection"))]
 mod tests {
     use parking_lot::{Mutex, deadlock};
     use std::{sync::Arc, thread, time::Duration};
 
+    // check_deadlock reports each deadlock once, to whichever test asks first
+    pub(super) static DEADLOCK_TESTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
     #[test]
     fn detects_deadlock_without_sleep() {
+        let _serial = DEADLOCK_TESTS.lock().unwrap_or_else(|e| e.into_inner());
         let a = Arc::new(Mutex::new(()));
         let b = Arc::new(Mutex::new(()));
 
@@ -0,0 +1,250 @@
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use parking_lot::deadlock;
+
+/// A thread stuck in a deadlock, as parking_lot found it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlockedThread {
+    /// parking_lot's id for the thread, `pthread_self()` on unix. Not the kernel TID that
+    /// `gettid` or a debugger shows.
+    pub thread_id: usize,
+    /// Where it's blocked, symbolized.
+    pub backtrace: String,
+}
+
+/// The deadlocks found in one check, each a cycle of threads waiting on each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlockReport {
+    pub cycles: Vec<Vec<DeadlockedThread>>,
+}
+
+impl fmt::Display for DeadlockReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} deadlock(s) found", self.cycles.len())?;
+        for (i, cycle) in self.cycles.iter().enumerate() {
+            write!(f, "\n\ndeadlock #{i}, {} threads", cycle.len())?;
+            for thread in cycle {
+                write!(f, "\n\nthread {}:\n{}", thread.thread_id, thread.backtrace)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the watchdog does with a [`DeadlockReport`].
+#[derive(Clone, Default)]
+pub enum OnDeadlock {
+    /// An error `tracing` event with the whole report.
+    #[default]
+    Log,
+    /// Logs it, prints it to stderr and aborts, so a supervisor restarts the process instead
+    /// of leaving it hung.
+    Abort,
+    Callback(Arc<dyn Fn(&DeadlockReport) + Send + Sync>),
+}
+
+impl fmt::Debug for OnDeadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnDeadlock::Log => f.write_str("Log"),
+            OnDeadlock::Abort => f.write_str("Abort"),
+            OnDeadlock::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+impl OnDeadlock {
+    pub fn callback(f: impl Fn(&DeadlockReport) + Send + Sync + 'static) -> Self {
+        OnDeadlock::Callback(Arc::new(f))
+    }
+
+    fn handle(&self, report: &DeadlockReport) {
+        match self {
+            OnDeadlock::Log => {
+                tracing::error!(deadlocks = report.cycles.len(), "{report}");
+            }
+            OnDeadlock::Abort => {
+                tracing::error!(deadlocks = report.cycles.len(), "{report}");
+                eprintln!("{report}");
+                std::process::abort();
+            }
+            OnDeadlock::Callback(callback) => callback(report),
+        }
+    }
+}
+
+/// A background thread that asks parking_lot for deadlocks every so often, so a long running
+/// service reports them in its logs instead of just going quiet.
+///
+/// Only locks from parking_lot (including [`OrderedMutex`](super::OrderedMutex)) are seen, and
+/// each deadlock is reported once. The thread stops when the watchdog is dropped.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use rust_learning::parking_lot::{DeadlockWatchdog, OnDeadlock};
+///
+/// let _watchdog = DeadlockWatchdog::builder(Duration::from_secs(10))
+///     .on_deadlock(OnDeadlock::Abort)
+///     .spawn();
+/// ```
+#[derive(Debug)]
+pub struct DeadlockWatchdog {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Configures a [`DeadlockWatchdog`] before it starts.
+#[derive(Debug)]
+pub struct WatchdogBuilder {
+    interval: Duration,
+    on_deadlock: OnDeadlock,
+}
+
+impl WatchdogBuilder {
+    pub fn on_deadlock(mut self, on_deadlock: OnDeadlock) -> Self {
+        self.on_deadlock = on_deadlock;
+        self
+    }
+
+    pub fn spawn(self) -> DeadlockWatchdog {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("deadlock-watchdog".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(self.interval) {
+                    if let Some(report) = check() {
+                        self.on_deadlock.handle(&report);
+                    }
+                }
+            })
+            .expect("failed to spawn the deadlock watchdog");
+        DeadlockWatchdog {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+// the deadlocks found since the last check, if any
+fn check() -> Option<DeadlockReport> {
+    let cycles = deadlock::check_deadlock();
+    if cycles.is_empty() {
+        return None;
+    }
+    let cycles = cycles
+        .iter()
+        .map(|cycle| {
+            cycle
+                .iter()
+                .map(|thread| DeadlockedThread {
+                    thread_id: thread.thread_id(),
+                    backtrace: format!("{:?}", thread.backtrace()),
+                })
+                .collect()
+        })
+        .collect();
+    Some(DeadlockReport { cycles })
+}
+
+impl DeadlockWatchdog {
+    /// Checks every `interval` and logs what it finds.
+    pub fn spawn(interval: Duration) -> Self {
+        Self::builder(interval).spawn()
+    }
+
+    pub fn builder(interval: Duration) -> WatchdogBuilder {
+        WatchdogBuilder {
+            interval,
+            on_deadlock: OnDeadlock::default(),
+        }
+    }
+
+    /// Stops the thread and waits for it, which can take up to the end of a check.
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DeadlockWatchdog {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parking_lot::tests::DEADLOCK_TESTS;
+    use parking_lot::Mutex;
+    use pretty_assertions::assert_eq;
+    use std::sync::Barrier;
+
+    #[test]
+    fn reports_cycles_to_the_callback() {
+        let _serial = DEADLOCK_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let (tx, reports) = mpsc::channel();
+        let watchdog = DeadlockWatchdog::builder(Duration::from_millis(10))
+            .on_deadlock(OnDeadlock::callback(move |report| {
+                let _ = tx.send(report.clone());
+            }))
+            .spawn();
+
+        // two threads that lock the same pair the other way round, and stay stuck forever
+        let locks = Arc::new((Mutex::new(()), Mutex::new(())));
+        let ready = Arc::new(Barrier::new(2));
+        for flipped in [false, true] {
+            let (locks, ready) = (locks.clone(), ready.clone());
+            thread::spawn(move || {
+                let (first, second) = if flipped {
+                    (&locks.1, &locks.0)
+                } else {
+                    (&locks.0, &locks.1)
+                };
+                let _first = first.lock();
+                ready.wait();
+                let _second = second.lock();
+            });
+        }
+
+        let report = reports.recv_timeout(Duration::from_secs(5)).unwrap();
+        watchdog.stop();
+
+        assert_eq!(report.cycles.len(), 1);
+        let cycle = &report.cycles[0];
+        assert_eq!(cycle.len(), 2);
+        assert_ne!(cycle[0].thread_id, cycle[1].thread_id);
+        assert!(
+            cycle
+                .iter()
+                .all(|thread| thread.backtrace.contains("reports_cycles_to_the_callback"))
+        );
+        assert!(
+            report
+                .to_string()
+                .starts_with("1 deadlock(s) found\n\ndeadlock #0, 2 threads")
+        );
+    }
+
+    #[test]
+    fn stops_when_dropped() {
+        let watchdog = DeadlockWatchdog::spawn(Duration::from_secs(3600));
+        let started = std::time::Instant::now();
+        drop(watchdog);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,306 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::repository::Song;
+use crate::errors::CustomError;
+
+/// Position in the `song_changes` outbox. A stream started from a cursor yields the changes
+/// after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cursor(pub i64);
+
+impl Cursor {
+    /// Before the first change.
+    pub const START: Cursor = Cursor(0);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// One mutation of the `songs` table, recorded by a trigger in the same transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub cursor: Cursor,
+    pub kind: ChangeKind,
+    /// The song after the change, or as it was before a delete.
+    pub song: Song,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeOptions {
+    /// How long to wait before looking again once the outbox is drained.
+    pub poll_interval: Duration,
+    /// Rows read per query, and how many changes the stream buffers ahead of its consumer.
+    pub batch_size: u32,
+}
+
+impl Default for ChangeOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(100),
+            batch_size: 100,
+        }
+    }
+}
+
+/// Streams changes after `since`, waiting for new ones when it runs out. Never ends by
+/// itself; drop it to stop.
+///
+/// Delivery is at least once. Store how far you got with [`ack`] after handling a change,
+/// and resume from [`cursor`] after a restart; anything you hadn't acked is delivered again.
+pub fn changes(pool: SqlitePool, since: Cursor) -> ReceiverStream<Change> {
+    changes_with(pool, since, ChangeOptions::default())
+}
+
+pub fn changes_with(
+    pool: SqlitePool,
+    since: Cursor,
+    options: ChangeOptions,
+) -> ReceiverStream<Change> {
+    let (tx, rx) = mpsc::channel(options.batch_size.max(1) as usize);
+
+    tokio::spawn(async move {
+        let mut cursor = since;
+        loop {
+            match fetch(&pool, cursor, options.batch_size).await {
+                Ok(batch) => {
+                    let drained = batch.len() < options.batch_size as usize;
+                    for change in batch {
+                        cursor = change.cursor;
+                        if tx.send(change).await.is_err() {
+                            return;
+                        }
+                    }
+                    if !drained {
+                        continue;
+                    }
+                }
+                // the next poll retries from the same cursor, so nothing is lost
+                Err(e) => tracing::warn!(error = %e, "failed to read song changes"),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(options.poll_interval) => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+async fn fetch(pool: &SqlitePool, after: Cursor, limit: u32) -> Result<Vec<Change>, CustomError> {
+    let rows = sqlx::query!(
+        r#"SELECT id as "id!", kind as "kind: ChangeKind", song_id, artist, title
+        FROM song_changes
+        WHERE id > ?
+        ORDER BY id
+        LIMIT ?"#,
+        after.0,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Change {
+            cursor: Cursor(row.id),
+            kind: row.kind,
+            song: Song {
+                id: row.song_id,
+                artist: row.artist,
+                title: row.title,
+            },
+        })
+        .collect())
+}
+
+/// Records that `consumer` has handled everything up to and including `cursor`. A cursor
+/// never moves backwards, so acking out of order is harmless.
+pub async fn ack(pool: &SqlitePool, consumer: &str, cursor: Cursor) -> Result<(), CustomError> {
+    sqlx::query!(
+        "INSERT INTO change_cursors (consumer, position) VALUES (?, ?)
+        ON CONFLICT (consumer) DO UPDATE SET position = MAX(position, excluded.position)",
+        consumer,
+        cursor.0
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Where `consumer` should resume from, [`Cursor::START`] if it has never acked anything.
+pub async fn cursor(pool: &SqlitePool, consumer: &str) -> Result<Cursor, CustomError> {
+    let position = sqlx::query_scalar!(
+        "SELECT position FROM change_cursors WHERE consumer = ?",
+        consumer
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(position.map(Cursor).unwrap_or(Cursor::START))
+}
+
+/// Deletes the changes every consumer has acked and returns how many went.
+///
+/// Only consumers that have acked at least once hold rows back. One that starts later from
+/// [`Cursor::START`] won't see what was compacted before it.
+pub async fn compact(pool: &SqlitePool) -> Result<u64, CustomError> {
+    let result = sqlx::query!(
+        "DELETE FROM song_changes WHERE id <= (SELECT MIN(position) FROM change_cursors)"
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlx::repository::{NewSong, SongRepository, SqliteSongRepository};
+    use pretty_assertions::assert_eq;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tokio_stream::StreamExt;
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn song(artist: &str, title: &str) -> NewSong {
+        NewSong {
+            artist: artist.to_owned(),
+            title: title.to_owned(),
+        }
+    }
+
+    fn fast() -> ChangeOptions {
+        ChangeOptions {
+            poll_interval: Duration::from_millis(5),
+            batch_size: 2,
+        }
+    }
+
+    async fn take(stream: &mut ReceiverStream<Change>, n: usize) -> Vec<(ChangeKind, String)> {
+        let mut out = Vec::new();
+        for _ in 0..n {
+            let change = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .expect("no change arrived")
+                .unwrap();
+            out.push((change.kind, change.song.title));
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn streams_every_mutation_in_order() {
+        let pool = pool().await;
+        let repo = SqliteSongRepository::new(pool.clone());
+        let lemon = repo.create(&song("Kenshi Yonezu", "Lemon")).await.unwrap();
+        repo.update(lemon.id, &song("Kenshi Yonezu", "Kick Back"))
+            .await
+            .unwrap();
+        repo.create(&song("YOASOBI", "Idol")).await.unwrap();
+        repo.delete(lemon.id).await.unwrap();
+
+        // more changes than one batch
+        let mut stream = changes_with(pool, Cursor::START, fast());
+        assert_eq!(
+            take(&mut stream, 4).await,
+            [
+                (ChangeKind::Insert, "Lemon".to_owned()),
+                (ChangeKind::Update, "Kick Back".to_owned()),
+                (ChangeKind::Insert, "Idol".to_owned()),
+                (ChangeKind::Delete, "Kick Back".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_new_changes() {
+        let pool = pool().await;
+        let repo = SqliteSongRepository::new(pool.clone());
+        let mut stream = changes_with(pool, Cursor::START, fast());
+
+        let next = tokio::spawn(async move { take(&mut stream, 1).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let idol = repo.create(&song("YOASOBI", "Idol")).await.unwrap();
+
+        assert_eq!(next.await.unwrap(), [(ChangeKind::Insert, idol.title)]);
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_acked_cursor() {
+        let pool = pool().await;
+        let repo = SqliteSongRepository::new(pool.clone());
+        for title in ["one", "two", "three"] {
+            repo.create(&song("artist", title)).await.unwrap();
+        }
+
+        let mut stream = changes_with(pool.clone(), cursor(&pool, "mailer").await.unwrap(), fast());
+        let first = stream.next().await.unwrap();
+        ack(&pool, "mailer", first.cursor).await.unwrap();
+        // handled but not acked, e.g. the consumer crashed here
+        stream.next().await.unwrap();
+        drop(stream);
+
+        let since = cursor(&pool, "mailer").await.unwrap();
+        assert_eq!(since, first.cursor);
+        let mut stream = changes_with(pool.clone(), since, fast());
+        assert_eq!(
+            take(&mut stream, 2).await,
+            [
+                (ChangeKind::Insert, "two".to_owned()),
+                (ChangeKind::Insert, "three".to_owned()),
+            ]
+        );
+
+        // acks don't move the cursor back
+        ack(&pool, "mailer", Cursor::START).await.unwrap();
+        assert_eq!(cursor(&pool, "mailer").await.unwrap(), first.cursor);
+        assert_eq!(cursor(&pool, "unknown").await.unwrap(), Cursor::START);
+    }
+
+    #[tokio::test]
+    async fn compacts_what_everyone_acked() {
+        let pool = pool().await;
+        let repo = SqliteSongRepository::new(pool.clone());
+        for title in ["one", "two", "three"] {
+            repo.create(&song("artist", title)).await.unwrap();
+        }
+        let all = fetch(&pool, Cursor::START, 10).await.unwrap();
+
+        // nobody has acked anything yet
+        assert_eq!(compact(&pool).await.unwrap(), 0);
+
+        ack(&pool, "mailer", all[2].cursor).await.unwrap();
+        ack(&pool, "indexer", all[0].cursor).await.unwrap();
+        assert_eq!(compact(&pool).await.unwrap(), 1);
+
+        let left = fetch(&pool, Cursor::START, 10).await.unwrap();
+        assert_eq!(left, all[1..]);
+
+        // ids aren't reused once the outbox is empty, so stored cursors stay valid
+        ack(&pool, "indexer", all[2].cursor).await.unwrap();
+        assert_eq!(compact(&pool).await.unwrap(), 2);
+        repo.create(&song("artist", "four")).await.unwrap();
+        let latest = fetch(&pool, all[2].cursor, 10).await.unwrap();
+        assert_eq!(latest[0].cursor, Cursor(all[2].cursor.0 + 1));
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+/// A column of the `songs_fts` index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FtsColumn {
+    Artist,
+    Title,
+}
+
+impl FtsColumn {
+    fn name(self) -> &'static str {
+        match self {
+            FtsColumn::Artist => "artist",
+            FtsColumn::Title => "title",
+        }
+    }
+}
+
+/// An FTS5 `MATCH` expression built from user input.
+///
+/// Every word the user typed has to match the start of a word in the song, so `kens yon`
+/// finds "Kenshi Yonezu". Quotes, `*`, `:`, `AND`/`OR`/`NOT` and the like are matched as
+/// text rather than parsed as FTS5 syntax.
+///
+/// ```
+/// use rust_learning::sqlx::fts::FtsQuery;
+///
+/// let query = FtsQuery::new("kick \"back").title("lem");
+/// assert_eq!(query.to_string(), r#""kick"* """back"* title : "lem"*"#);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FtsQuery {
+    terms: Vec<String>,
+}
+
+impl FtsQuery {
+    /// Matches `input` against any column.
+    pub fn new(input: &str) -> Self {
+        Self::default().any(input)
+    }
+
+    /// Also requires every word of `input`, in any column.
+    pub fn any(mut self, input: &str) -> Self {
+        self.terms.extend(phrases(input));
+        self
+    }
+
+    /// Also requires every word of `input` in the artist.
+    pub fn artist(self, input: &str) -> Self {
+        self.column(FtsColumn::Artist, input)
+    }
+
+    /// Also requires every word of `input` in the title.
+    pub fn title(self, input: &str) -> Self {
+        self.column(FtsColumn::Title, input)
+    }
+
+    pub fn column(mut self, column: FtsColumn, input: &str) -> Self {
+        let name = column.name();
+        self.terms
+            .extend(phrases(input).map(|phrase| format!("{name} : {phrase}")));
+        self
+    }
+
+    /// True if the input had no words in it. FTS5 rejects an empty `MATCH`, so there's
+    /// nothing to run.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+impl fmt::Display for FtsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.terms.join(" "))
+    }
+}
+
+// Each word becomes a quoted prefix phrase. Inside quotes FTS5 only treats `"` specially, and
+// doubling it escapes it. Words without letters or digits would tokenize to nothing, which
+// FTS5 doesn't accept as a phrase, so they're dropped.
+fn phrases(input: &str) -> impl Iterator<Item = String> + '_ {
+    input
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn quotes_every_word_as_a_prefix() {
+        assert_eq!(
+            FtsQuery::new("  kenshi   yon ").to_string(),
+            r#""kenshi"* "yon"*"#
+        );
+    }
+
+    #[test]
+    fn escapes_fts_syntax() {
+        assert_eq!(
+            FtsQuery::new(r#"a" OR title:x NOT (y*) NEAR"#).to_string(),
+            r#""a"""* "OR"* "title:x"* "NOT"* "(y*)"* "NEAR"*"#
+        );
+    }
+
+    #[test]
+    fn scopes_columns() {
+        let query = FtsQuery::default().artist("kenshi yonezu").title("lemon");
+        assert_eq!(
+            query.to_string(),
+            r#"artist : "kenshi"* artist : "yonezu"* title : "lemon"*"#
+        );
+    }
+
+    #[test]
+    fn drops_words_without_text() {
+        assert!(FtsQuery::new("").is_empty());
+        assert!(FtsQuery::new(" * \" - ").title("()").is_empty());
+        assert_eq!(FtsQuery::new("- idol -").to_string(), r#""idol"*"#);
+    }
+}
@@ -0,0 +1,911 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::FutureExt;
+use serde::{Serialize, de::DeserializeOwned};
+use sqlx::SqlitePool;
+use tokio::{
+    sync::{Semaphore, watch},
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use super::transaction::{RetryPolicy, with_transaction};
+use crate::errors::{CustomError, CustomErrorKind};
+
+pub type JobId = i64;
+
+/// When a job becomes due and how it's ordered against other due jobs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Schedule {
+    run_at: Option<SystemTime>,
+    priority: i64,
+}
+
+impl Schedule {
+    pub fn at(mut self, time: SystemTime) -> Self {
+        self.run_at = Some(time);
+        self
+    }
+
+    pub fn after(self, delay: Duration) -> Self {
+        self.at(SystemTime::now() + delay)
+    }
+
+    /// Higher runs first. Defaults to 0.
+    pub fn priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// A job that failed for good, kept for inspection until it's requeued or discarded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadJob {
+    pub id: JobId,
+    /// The JSON the job was enqueued with.
+    pub payload: String,
+    pub priority: i64,
+    pub attempts: u32,
+    pub error: String,
+    pub failed_at: SystemTime,
+}
+
+/// A named queue of `T`s in the `jobs` table, shared by every process using the database.
+///
+/// ```ignore
+/// let queue = JobQueue::<Email>::new(pool, "emails");
+/// queue.schedule(&email, Schedule::default().after(Duration::from_secs(60))).await?;
+///
+/// let workers = queue.worker(|email| async move { send(email).await }).concurrency(8).spawn();
+/// // ...
+/// workers.shutdown().await;
+/// ```
+pub struct JobQueue<T> {
+    pool: SqlitePool,
+    name: String,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for JobQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            name: self.name.clone(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+fn not_found(id: JobId) -> CustomError {
+    CustomError::new(
+        CustomErrorKind::NotFound,
+        format!("no dead job with id {id}"),
+    )
+}
+
+impl<T> JobQueue<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    pub fn new(pool: SqlitePool, name: impl Into<String>) -> Self {
+        Self {
+            pool,
+            name: name.into(),
+            _payload: PhantomData,
+        }
+    }
+
+    /// Adds a job that's due straight away.
+    pub async fn enqueue(&self, payload: &T) -> Result<JobId, CustomError> {
+        self.schedule(payload, Schedule::default()).await
+    }
+
+    pub async fn schedule(&self, payload: &T, schedule: Schedule) -> Result<JobId, CustomError> {
+        let payload = serde_json::to_string(payload).map_err(|e| {
+            CustomError::new(CustomErrorKind::InvalidArgument, "unserializable job").with_source(e)
+        })?;
+        let run_at = millis(schedule.run_at.unwrap_or_else(SystemTime::now));
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO jobs (queue, payload, priority, run_at) VALUES (?, ?, ?, ?)
+            RETURNING id as "id!""#,
+            self.name,
+            payload,
+            schedule.priority,
+            run_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    /// Sets up workers that run `handler` on this queue's jobs. Nothing runs until
+    /// [`Worker::spawn`].
+    pub fn worker<F, Fut>(&self, handler: F) -> Worker<T, F>
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), CustomError>> + Send + 'static,
+    {
+        Worker {
+            queue: self.clone(),
+            handler,
+            concurrency: 4,
+            lease: Duration::from_secs(30),
+            heartbeat: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(500),
+            retry: RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(10 * 60),
+            },
+        }
+    }
+
+    /// Dead jobs of this queue, most recent failure first.
+    pub async fn dead_jobs(&self, limit: u32) -> Result<Vec<DeadJob>, CustomError> {
+        let rows = sqlx::query!(
+            r#"SELECT id as "id!", payload, priority, attempts, error, failed_at
+            FROM dead_jobs
+            WHERE queue = ?
+            ORDER BY failed_at DESC, id DESC
+            LIMIT ?"#,
+            self.name,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| DeadJob {
+                id: row.id,
+                payload: row.payload,
+                priority: row.priority,
+                attempts: row.attempts as u32,
+                error: row.error,
+                failed_at: UNIX_EPOCH + Duration::from_millis(row.failed_at as u64),
+            })
+            .collect())
+    }
+
+    /// Moves a dead job back onto the queue, due now and with its attempts reset. It comes
+    /// back as a new job, under the id returned.
+    pub async fn requeue_dead(&self, id: JobId) -> Result<JobId, CustomError> {
+        let queue = self.name.clone();
+        let now = millis(SystemTime::now());
+        with_transaction(&self.pool, move |tx| {
+            let queue = queue.clone();
+            Box::pin(async move {
+                let requeued = sqlx::query_scalar!(
+                    r#"INSERT INTO jobs (queue, payload, priority, run_at)
+                    SELECT queue, payload, priority, ? FROM dead_jobs WHERE id = ? AND queue = ?
+                    RETURNING id as "id!""#,
+                    now,
+                    id,
+                    queue
+                )
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| not_found(id))?;
+                sqlx::query!("DELETE FROM dead_jobs WHERE id = ?", id)
+                    .execute(&mut *tx)
+                    .await?;
+                Ok(requeued)
+            })
+        })
+        .await
+    }
+
+    pub async fn discard_dead(&self, id: JobId) -> Result<(), CustomError> {
+        let result = sqlx::query!(
+            "DELETE FROM dead_jobs WHERE id = ? AND queue = ?",
+            id,
+            self.name
+        )
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id));
+        }
+        Ok(())
+    }
+
+    // Takes the best due job whose lease is free or has run out, so a crashed worker's job
+    // is picked up again once its lease expires. Counts as an attempt even if the worker
+    // dies before finishing, so a job that keeps crashing workers still ends up dead.
+    async fn claim(&self, worker: &str, lease: Duration) -> Result<Option<Claimed>, CustomError> {
+        let now = millis(SystemTime::now());
+        let locked_until = now + lease.as_millis() as i64;
+        let row = sqlx::query!(
+            r#"UPDATE jobs SET locked_by = ?1, locked_until = ?2, attempts = attempts + 1
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE queue = ?3 AND run_at <= ?4 AND (locked_until IS NULL OR locked_until <= ?4)
+                ORDER BY priority DESC, run_at, id
+                LIMIT 1
+            )
+            RETURNING id as "id!", payload, attempts, last_error"#,
+            worker,
+            locked_until,
+            self.name,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| Claimed {
+            id: row.id,
+            payload: row.payload,
+            attempts: row.attempts as u32,
+            last_error: row.last_error,
+        }))
+    }
+}
+
+struct Claimed {
+    id: JobId,
+    payload: String,
+    attempts: u32,
+    last_error: Option<String>,
+}
+
+/// Builder for a pool of workers on one [`JobQueue`].
+pub struct Worker<T, F> {
+    queue: JobQueue<T>,
+    handler: F,
+    concurrency: usize,
+    lease: Duration,
+    heartbeat: Duration,
+    poll_interval: Duration,
+    retry: RetryPolicy,
+}
+
+impl<T, F, Fut> Worker<T, F>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), CustomError>> + Send + 'static,
+{
+    /// Jobs run at the same time. Defaults to 4.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How long a job stays claimed without a heartbeat before another worker may take it.
+    /// Defaults to 30 seconds, renewed every 10.
+    ///
+    /// Panics unless `heartbeat` is non-zero and shorter than `lease`, since otherwise a job
+    /// that's still running could lose its lease and run twice.
+    pub fn lease(mut self, lease: Duration, heartbeat: Duration) -> Self {
+        assert!(
+            !heartbeat.is_zero() && heartbeat < lease,
+            "the heartbeat ({heartbeat:?}) has to be non-zero and shorter than the lease ({lease:?})"
+        );
+        self.lease = lease;
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// How long to wait before looking again when no job is due. Defaults to 500ms.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Backoff between attempts, and how many a job gets before it's dead-lettered. Only
+    /// [retryable](CustomErrorKind::is_retryable) errors are retried. Defaults to 5 attempts,
+    /// 1 second growing to 10 minutes apart.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn spawn(self) -> WorkerHandle {
+        let (stop, mut stopped) = watch::channel(false);
+        let concurrency = self.concurrency;
+        let worker = Arc::new(Running {
+            id: Uuid::now_v7().to_string(),
+            worker: self,
+        });
+
+        let task = tokio::spawn(async move {
+            let permits = Arc::new(Semaphore::new(concurrency));
+            loop {
+                let permit = tokio::select! {
+                    permit = permits.clone().acquire_owned() => permit.expect("never closed"),
+                    _ = stopped.changed() => break,
+                };
+                match worker
+                    .worker
+                    .queue
+                    .claim(&worker.id, worker.worker.lease)
+                    .await
+                {
+                    Ok(Some(job)) => {
+                        let worker = worker.clone();
+                        tokio::spawn(async move {
+                            worker.run(job).await;
+                            drop(permit);
+                        });
+                        continue;
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!(error = %e, "failed to claim a job"),
+                }
+                drop(permit);
+                tokio::select! {
+                    _ = tokio::time::sleep(worker.worker.poll_interval) => {}
+                    _ = stopped.changed() => break,
+                }
+            }
+            // let the jobs that are running finish
+            let _ = permits.acquire_many(concurrency as u32).await;
+        });
+
+        WorkerHandle { stop, task }
+    }
+}
+
+struct Running<T, F> {
+    id: String,
+    worker: Worker<T, F>,
+}
+
+impl<T, F, Fut> Running<T, F>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), CustomError>> + Send + 'static,
+{
+    async fn run(&self, job: Claimed) {
+        let max_attempts = self.worker.retry.max_attempts;
+        if job.attempts > max_attempts {
+            let error = job
+                .last_error
+                .unwrap_or_else(|| "its worker stopped without finishing".to_owned());
+            return self.dead_letter(job.id, error).await;
+        }
+
+        let result = match serde_json::from_str::<T>(&job.payload) {
+            Ok(payload) => self.execute(job.id, payload).await,
+            Err(e) => Err(CustomError::new(
+                CustomErrorKind::InvalidData,
+                "job payload doesn't match the queue's type",
+            )
+            .with_source(e)),
+        };
+
+        match result {
+            Ok(()) => {
+                let done = sqlx::query!(
+                    "DELETE FROM jobs WHERE id = ? AND locked_by = ?",
+                    job.id,
+                    self.id
+                )
+                .execute(&self.worker.queue.pool)
+                .await;
+                if let Err(e) = done {
+                    tracing::warn!(job = job.id, error = %e, "failed to mark a job done");
+                }
+            }
+            Err(e) if e.kind().is_retryable() && job.attempts < max_attempts => {
+                let delay = self.worker.retry.delay(job.attempts);
+                tracing::info!(job = job.id, error = %e, ?delay, "job failed, retrying");
+                let run_at = millis(SystemTime::now() + delay);
+                let error = e.to_string();
+                let released = sqlx::query!(
+                    "UPDATE jobs SET run_at = ?, last_error = ?, locked_by = NULL, locked_until = NULL
+                    WHERE id = ? AND locked_by = ?",
+                    run_at,
+                    error,
+                    job.id,
+                    self.id
+                )
+                .execute(&self.worker.queue.pool)
+                .await;
+                if let Err(e) = released {
+                    tracing::warn!(job = job.id, error = %e, "failed to reschedule a job");
+                }
+            }
+            Err(e) => self.dead_letter(job.id, e.to_string()).await,
+        }
+    }
+
+    // runs the handler, renewing the lease until it finishes
+    async fn execute(&self, id: JobId, payload: T) -> Result<(), CustomError> {
+        // the handler itself can panic before it gets to returning a future
+        let handler = &self.worker.handler;
+        let work = AssertUnwindSafe(async move { handler(payload).await }).catch_unwind();
+        tokio::pin!(work);
+        let mut heartbeat = tokio::time::interval(self.worker.heartbeat);
+        heartbeat.tick().await;
+        loop {
+            tokio::select! {
+                result = &mut work => {
+                    return result.unwrap_or_else(|_| {
+                        Err(CustomError::new(CustomErrorKind::Other, "job panicked"))
+                    });
+                }
+                _ = heartbeat.tick() => self.renew(id).await,
+            }
+        }
+    }
+
+    async fn renew(&self, id: JobId) {
+        let locked_until = millis(SystemTime::now() + self.worker.lease);
+        let renewed = sqlx::query!(
+            "UPDATE jobs SET locked_until = ? WHERE id = ? AND locked_by = ?",
+            locked_until,
+            id,
+            self.id
+        )
+        .execute(&self.worker.queue.pool)
+        .await;
+        match renewed {
+            Ok(result) if result.rows_affected() == 0 => {
+                tracing::warn!(job = id, "lost the lease on a running job");
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(job = id, error = %e, "failed to renew a job's lease"),
+        }
+    }
+
+    async fn dead_letter(&self, id: JobId, error: String) {
+        tracing::warn!(job = id, error, "job failed for good");
+        let worker = self.id.clone();
+        let failed_at = millis(SystemTime::now());
+        let moved = with_transaction(&self.worker.queue.pool, move |tx| {
+            let worker = worker.clone();
+            let error = error.clone();
+            Box::pin(async move {
+                sqlx::query!(
+                    "INSERT INTO dead_jobs (id, queue, payload, priority, attempts, error, failed_at)
+                    SELECT id, queue, payload, priority, attempts, ?, ? FROM jobs
+                    WHERE id = ? AND locked_by = ?",
+                    error,
+                    failed_at,
+                    id,
+                    worker
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!("DELETE FROM jobs WHERE id = ? AND locked_by = ?", id, worker)
+                    .execute(&mut *tx)
+                    .await?;
+                Ok(())
+            })
+        })
+        .await;
+        if let Err(e) = moved {
+            tracing::warn!(job = id, error = %e, "failed to dead-letter a job");
+        }
+    }
+}
+
+/// Running workers. Dropping the handle stops them too, without waiting.
+pub struct WorkerHandle {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl WorkerHandle {
+    /// Stops claiming jobs and waits for the running ones to finish.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Email {
+        to: String,
+    }
+
+    fn email(to: &str) -> Email {
+        Email { to: to.to_owned() }
+    }
+
+    async fn queue() -> JobQueue<Email> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        JobQueue::new(pool, "emails")
+    }
+
+    fn quick(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        }
+    }
+
+    async fn pending(queue: &JobQueue<Email>) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM jobs")
+            .fetch_one(&queue.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn eventually<Fut: Future<Output = bool>>(mut check: impl FnMut() -> Fut) {
+        for _ in 0..500 {
+            if check().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never held");
+    }
+
+    #[tokio::test]
+    async fn runs_due_jobs_by_priority() {
+        let queue = queue().await;
+        queue.enqueue(&email("low")).await.unwrap();
+        queue
+            .schedule(&email("high"), Schedule::default().priority(10))
+            .await
+            .unwrap();
+        queue
+            .schedule(
+                &email("later"),
+                Schedule::default()
+                    .priority(100)
+                    .after(Duration::from_millis(300)),
+            )
+            .await
+            .unwrap();
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let workers = queue
+            .worker({
+                let sent = sent.clone();
+                move |email: Email| {
+                    let sent = sent.clone();
+                    async move {
+                        sent.lock().unwrap().push(email.to);
+                        Ok(())
+                    }
+                }
+            })
+            .concurrency(1)
+            .poll_interval(Duration::from_millis(5))
+            .spawn();
+
+        eventually(|| async { sent.lock().unwrap().len() == 2 }).await;
+        assert_eq!(*sent.lock().unwrap(), ["high", "low"]);
+        eventually(|| async { sent.lock().unwrap().len() == 3 }).await;
+        assert_eq!(sent.lock().unwrap()[2], "later");
+
+        workers.shutdown().await;
+        assert_eq!(pending(&queue).await, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_errors() {
+        let queue = queue().await;
+        queue.enqueue(&email("flaky")).await.unwrap();
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let workers = queue
+            .worker({
+                let calls = calls.clone();
+                move |_: Email| {
+                    let call = calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if call < 2 {
+                            return Err(CustomError::new(CustomErrorKind::TimedOut, "smtp"));
+                        }
+                        Ok(())
+                    }
+                }
+            })
+            .poll_interval(Duration::from_millis(5))
+            .retry(quick(5))
+            .spawn();
+
+        eventually(|| async { pending(&queue).await == 0 }).await;
+        workers.shutdown().await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(queue.dead_jobs(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dead_letters_permanent_failures_and_exhausted_retries() {
+        let queue = queue().await;
+        let rejected = queue.enqueue(&email("rejected")).await.unwrap();
+        let flaky = queue.enqueue(&email("flaky")).await.unwrap();
+        // same table, different payload type
+        let broken = JobQueue::<u32>::new(queue.pool.clone(), "emails")
+            .enqueue(&7)
+            .await
+            .unwrap();
+
+        let workers = queue
+            .worker(|email: Email| async move {
+                match email.to.as_str() {
+                    "rejected" => Err(CustomError::new(
+                        CustomErrorKind::InvalidArgument,
+                        "no such mailbox",
+                    )),
+                    _ => Err(CustomError::new(CustomErrorKind::ResourceBusy, "try later")),
+                }
+            })
+            .poll_interval(Duration::from_millis(5))
+            .retry(quick(3))
+            .spawn();
+
+        eventually(|| async { pending(&queue).await == 0 }).await;
+        workers.shutdown().await;
+
+        let mut dead = queue.dead_jobs(10).await.unwrap();
+        dead.sort_by_key(|job| job.id);
+        let summary = dead
+            .iter()
+            .map(|job| (job.id, job.attempts, job.error.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (rejected, 1, "InvalidArgument: no such mailbox".to_owned()),
+                (flaky, 3, "ResourceBusy: try later".to_owned()),
+                (
+                    broken,
+                    1,
+                    "InvalidData: job payload doesn't match the queue's type (source: invalid type: integer `7`, expected struct Email at line 1 column 1)".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(dead[0].payload, r#"{"to":"rejected"}"#);
+    }
+
+    #[tokio::test]
+    async fn requeues_and_discards_dead_jobs() {
+        let queue = queue().await;
+        let first = queue.enqueue(&email("first")).await.unwrap();
+        let second = queue.enqueue(&email("second")).await.unwrap();
+
+        let fixed = Arc::new(AtomicU32::new(0));
+        let workers = queue
+            .worker({
+                let fixed = fixed.clone();
+                move |_: Email| {
+                    let fixed = fixed.load(Ordering::SeqCst) > 0;
+                    async move {
+                        if fixed {
+                            Ok(())
+                        } else {
+                            Err(CustomError::new(CustomErrorKind::NotFound, "no template"))
+                        }
+                    }
+                }
+            })
+            .poll_interval(Duration::from_millis(5))
+            .spawn();
+
+        eventually(|| async { queue.dead_jobs(10).await.unwrap().len() == 2 }).await;
+        fixed.store(1, Ordering::SeqCst);
+
+        let requeued = queue.requeue_dead(first).await.unwrap();
+        assert!(requeued > second);
+        queue.discard_dead(second).await.unwrap();
+        eventually(|| async { pending(&queue).await == 0 }).await;
+        workers.shutdown().await;
+
+        assert!(queue.dead_jobs(10).await.unwrap().is_empty());
+        for missing in [
+            queue.requeue_dead(first).await.map(drop),
+            queue.discard_dead(second).await,
+        ] {
+            assert_eq!(missing.unwrap_err().kind(), CustomErrorKind::NotFound);
+        }
+    }
+
+    #[tokio::test]
+    async fn never_reuses_the_id_of_a_dead_job() {
+        let queue = queue().await;
+        let workers = queue
+            .worker(|_: Email| async {
+                Err(CustomError::new(
+                    CustomErrorKind::InvalidArgument,
+                    "no such mailbox",
+                ))
+            })
+            .poll_interval(Duration::from_millis(5))
+            .spawn();
+
+        // each one is the newest job when it's dead-lettered, and its row leaves `jobs`
+        let mut ids = Vec::new();
+        for to in ["first", "second"] {
+            ids.push(queue.enqueue(&email(to)).await.unwrap());
+            eventually(|| async { queue.dead_jobs(10).await.unwrap().len() == ids.len() }).await;
+        }
+        let requeued = queue.requeue_dead(ids[0]).await.unwrap();
+        eventually(|| async {
+            queue
+                .dead_jobs(10)
+                .await
+                .unwrap()
+                .iter()
+                .any(|job| job.id == requeued)
+        })
+        .await;
+        workers.shutdown().await;
+
+        assert_eq!(pending(&queue).await, 0);
+        let mut dead = queue
+            .dead_jobs(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect::<Vec<_>>();
+        dead.sort();
+        assert_eq!(dead, [ids[1], requeued]);
+        assert!(ids[0] < ids[1] && ids[1] < requeued);
+    }
+
+    #[tokio::test]
+    async fn handlers_that_panic_before_their_future_fail_the_job() {
+        let queue = queue().await;
+        let id = queue.enqueue(&email("boom")).await.unwrap();
+        let workers = queue
+            .worker(
+                |email: Email| -> std::future::Ready<Result<(), CustomError>> {
+                    panic!("no template for {}", email.to)
+                },
+            )
+            .poll_interval(Duration::from_millis(5))
+            .retry(quick(1))
+            .spawn();
+
+        eventually(|| async { pending(&queue).await == 0 }).await;
+        workers.shutdown().await;
+
+        let dead = queue.dead_jobs(10).await.unwrap();
+        assert_eq!(
+            dead.iter()
+                .map(|job| (job.id, job.error.as_str()))
+                .collect::<Vec<_>>(),
+            [(id, "Other: job panicked")]
+        );
+    }
+
+    #[tokio::test]
+    async fn reclaims_jobs_from_crashed_workers() {
+        let queue = queue().await;
+        let id = queue.enqueue(&email("orphan")).await.unwrap();
+        // a worker that claims the job and is never heard from again
+        let claimed = queue
+            .claim("crashed", Duration::from_millis(100))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(claimed.id, id);
+        assert!(
+            queue
+                .claim("other", Duration::from_secs(1))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let sent = Arc::new(AtomicU32::new(0));
+        let workers = queue
+            .worker({
+                let sent = sent.clone();
+                move |_: Email| {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    async { Ok(()) }
+                }
+            })
+            .poll_interval(Duration::from_millis(5))
+            .spawn();
+
+        eventually(|| async { sent.load(Ordering::SeqCst) == 1 }).await;
+        workers.shutdown().await;
+        assert_eq!(pending(&queue).await, 0);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_keep_slow_jobs_leased() {
+        let queue = queue().await;
+        queue.enqueue(&email("slow")).await.unwrap();
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let spawn = || {
+            let runs = runs.clone();
+            queue
+                .worker(move |_: Email| {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    async {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        Ok(())
+                    }
+                })
+                .lease(Duration::from_millis(60), Duration::from_millis(15))
+                .poll_interval(Duration::from_millis(5))
+                .spawn()
+        };
+        let (a, b) = (spawn(), spawn());
+
+        eventually(|| async { pending(&queue).await == 0 }).await;
+        a.shutdown().await;
+        b.shutdown().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "shorter than the lease")]
+    async fn heartbeats_have_to_beat_the_lease() {
+        queue()
+            .await
+            .worker(|_: Email| async { Ok(()) })
+            .lease(Duration::from_secs(10), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "has to be non-zero")]
+    async fn heartbeats_cant_be_zero() {
+        queue()
+            .await
+            .worker(|_: Email| async { Ok(()) })
+            .lease(Duration::from_secs(10), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_running_jobs() {
+        let queue = queue().await;
+        queue.enqueue(&email("slow")).await.unwrap();
+
+        let (started_tx, started) = tokio::sync::oneshot::channel();
+        let started_tx = Mutex::new(Some(started_tx));
+        let finished = Arc::new(AtomicU32::new(0));
+        let workers = queue
+            .worker({
+                let finished = finished.clone();
+                move |_: Email| {
+                    if let Some(tx) = started_tx.lock().unwrap().take() {
+                        tx.send(()).unwrap();
+                    }
+                    let finished = finished.clone();
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        finished.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    }
+                }
+            })
+            .poll_interval(Duration::from_millis(5))
+            .spawn();
+
+        started.await.unwrap();
+        workers.shutdown().await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        assert_eq!(pending(&queue).await, 0);
+    }
+}
@@ -0,0 +1,534 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use sqlx::{
+    SqliteConnection,
+    migrate::{Migrate, MigrateError, Migration, MigrationType, Migrator},
+};
+
+use crate::errors::{CustomError, CustomErrorKind};
+
+impl From<MigrateError> for CustomError {
+    fn from(error: MigrateError) -> Self {
+        let kind = match &error {
+            MigrateError::Execute(_) | MigrateError::ExecuteMigration(..) => CustomErrorKind::Other,
+            MigrateError::Source(_) => CustomErrorKind::InvalidArgument,
+            MigrateError::VersionMissing(_) => CustomErrorKind::NotFound,
+            MigrateError::VersionMismatch(_) | MigrateError::Dirty(_) => {
+                CustomErrorKind::InvalidData
+            }
+            _ => CustomErrorKind::Other,
+        };
+        CustomError::new(kind, "migration failed").with_source(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the file has been edited since.
+    Modified,
+    /// Applied, but the file is gone.
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    pub reversible: bool,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "MODIFIED",
+            MigrationState::Missing => "MISSING",
+        };
+        write!(f, "{:>14} {:<8} {}", self.version, state, self.description)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// A migration that ran, or would have in a dry run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub version: i64,
+    pub description: String,
+    pub direction: Direction,
+    pub sql: String,
+}
+
+/// Up, down and status over a directory of `<version>_<name>.sql` or
+/// `<version>_<name>.{up,down}.sql` files, tracked in the same `_sqlx_migrations` table
+/// `sqlx::migrate!` uses.
+pub struct Migrations {
+    migrator: Migrator,
+}
+
+impl Migrations {
+    pub async fn load(dir: impl AsRef<Path>) -> Result<Self, CustomError> {
+        Ok(Self {
+            migrator: Migrator::new(dir.as_ref()).await?,
+        })
+    }
+
+    fn ups(&self) -> impl Iterator<Item = &Migration> {
+        self.migrator
+            .iter()
+            .filter(|m| m.migration_type.is_up_migration())
+    }
+
+    fn down_for(&self, version: i64) -> Option<&Migration> {
+        self.migrator
+            .iter()
+            .find(|m| m.version == version && m.migration_type.is_down_migration())
+    }
+
+    /// Only reads, so it works over a read-only connection, even to a database that was never
+    /// migrated.
+    pub async fn status(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<MigrationStatus>, CustomError> {
+        let applied = if has_migrations_table(conn).await? {
+            conn.list_applied_migrations().await?
+        } else {
+            Vec::new()
+        };
+        let mut applied: BTreeMap<i64, _> = applied
+            .into_iter()
+            .map(|m| (m.version, m.checksum))
+            .collect();
+
+        let mut statuses: Vec<_> = self
+            .ups()
+            .map(|m| {
+                let state = match applied.remove(&m.version) {
+                    None => MigrationState::Pending,
+                    Some(checksum) if checksum == m.checksum => MigrationState::Applied,
+                    Some(_) => MigrationState::Modified,
+                };
+                MigrationStatus {
+                    version: m.version,
+                    description: m.description.to_string(),
+                    state,
+                    reversible: m.migration_type == MigrationType::ReversibleUp,
+                }
+            })
+            .collect();
+        statuses.extend(applied.into_keys().map(|version| MigrationStatus {
+            version,
+            description: String::new(),
+            state: MigrationState::Missing,
+            reversible: false,
+        }));
+        statuses.sort_by_key(|s| s.version);
+        Ok(statuses)
+    }
+
+    // refuses to go anywhere while the history on disk disagrees with the database
+    async fn verified_status(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<MigrationStatus>, CustomError> {
+        let statuses = self.status(conn).await?;
+        let modified: Vec<String> = statuses
+            .iter()
+            .filter(|s| s.state == MigrationState::Modified)
+            .map(|s| s.version.to_string())
+            .collect();
+        if !modified.is_empty() {
+            return Err(CustomError::new(
+                CustomErrorKind::InvalidData,
+                format!(
+                    "applied migrations were edited afterwards: {}",
+                    modified.join(", ")
+                ),
+            ));
+        }
+        if has_migrations_table(conn).await?
+            && let Some(version) = conn.dirty_version().await?
+        {
+            return Err(MigrateError::Dirty(version).into());
+        }
+        Ok(statuses)
+    }
+
+    /// Applies pending migrations in order, up to and including `to` if given. A dry run
+    /// only reads, like [`Migrations::status`].
+    pub async fn up(
+        &self,
+        conn: &mut SqliteConnection,
+        to: Option<i64>,
+        dry_run: bool,
+    ) -> Result<Vec<Step>, CustomError> {
+        if !dry_run {
+            conn.ensure_migrations_table().await?;
+        }
+        let statuses = self.verified_status(conn).await?;
+        let pending: Vec<i64> = statuses
+            .iter()
+            .filter(|s| s.state == MigrationState::Pending)
+            .filter(|s| to.is_none_or(|to| s.version <= to))
+            .map(|s| s.version)
+            .collect();
+
+        let mut steps = Vec::new();
+        for migration in self.ups().filter(|m| pending.contains(&m.version)) {
+            if !dry_run {
+                conn.apply(migration).await?;
+            }
+            steps.push(Step {
+                version: migration.version,
+                description: migration.description.to_string(),
+                direction: Direction::Up,
+                sql: migration.sql.to_string(),
+            });
+        }
+        Ok(steps)
+    }
+
+    /// Reverts the last `steps` applied migrations, newest first. A dry run only reads, like
+    /// [`Migrations::status`].
+    pub async fn down(
+        &self,
+        conn: &mut SqliteConnection,
+        steps: usize,
+        dry_run: bool,
+    ) -> Result<Vec<Step>, CustomError> {
+        if !dry_run {
+            conn.ensure_migrations_table().await?;
+        }
+        let statuses = self.verified_status(conn).await?;
+        let applied = statuses
+            .iter()
+            .rev()
+            .filter(|s| matches!(s.state, MigrationState::Applied | MigrationState::Missing))
+            .take(steps);
+
+        // work out every down script before running any of them
+        let mut migrations = Vec::new();
+        for status in applied {
+            let migration = self.down_for(status.version).ok_or_else(|| {
+                CustomError::new(
+                    CustomErrorKind::NotFound,
+                    format!("migration {} has no down script", status.version),
+                )
+            })?;
+            migrations.push(migration);
+        }
+
+        let mut steps = Vec::new();
+        for migration in migrations {
+            if !dry_run {
+                conn.revert(migration).await?;
+            }
+            steps.push(Step {
+                version: migration.version,
+                description: migration.description.to_string(),
+                direction: Direction::Down,
+                sql: migration.sql.to_string(),
+            });
+        }
+        Ok(steps)
+    }
+}
+
+// `ensure_migrations_table` would create it, which a read-only connection can't
+async fn has_migrations_table(conn: &mut SqliteConnection) -> Result<bool, CustomError> {
+    let exists = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(exists)
+}
+
+/// Creates an empty `<timestamp>_<name>.up.sql` and `.down.sql` pair in `dir`.
+pub fn new_migration(dir: impl AsRef<Path>, name: &str) -> Result<[PathBuf; 2], CustomError> {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.trim_matches('_').is_empty() {
+        return Err(CustomError::new(
+            CustomErrorKind::InvalidArgument,
+            "migration name can't be empty",
+        ));
+    }
+
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).map_err(io_error)?;
+    let version = timestamp(SystemTime::now());
+    let up = dir.join(format!("{version}_{name}.up.sql"));
+    let down = dir.join(format!("{version}_{name}.down.sql"));
+    for (path, content) in [
+        (&up, "-- Add up migration script here\n"),
+        (&down, "-- Add down migration script here\n"),
+    ] {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+            .map_err(io_error)?;
+    }
+    Ok([up, down])
+}
+
+fn io_error(error: std::io::Error) -> CustomError {
+    let kind = match error.kind() {
+        std::io::ErrorKind::NotFound => CustomErrorKind::NotFound,
+        std::io::ErrorKind::PermissionDenied => CustomErrorKind::PermissionDenied,
+        _ => CustomErrorKind::Other,
+    };
+    CustomError::new(kind, "couldn't write migration").with_source(error)
+}
+
+// `YYYYMMDDhhmmss` in UTC, the same versions `sqlx migrate add` uses
+fn timestamp(now: SystemTime) -> String {
+    let secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sqlx::{Connection, sqlite::SqliteConnectOptions};
+    use std::time::Duration;
+
+    fn write(dir: &Path, name: &str, sql: &str) {
+        fs::write(dir.join(name), sql).unwrap();
+    }
+
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "1_create_artists.up.sql",
+            "CREATE TABLE artists (id INTEGER PRIMARY KEY, name TEXT NOT NULL);",
+        );
+        write(
+            dir.path(),
+            "1_create_artists.down.sql",
+            "DROP TABLE artists;",
+        );
+        write(
+            dir.path(),
+            "2_add_country.up.sql",
+            "ALTER TABLE artists ADD COLUMN country TEXT;",
+        );
+        write(
+            dir.path(),
+            "2_add_country.down.sql",
+            "ALTER TABLE artists DROP COLUMN country;",
+        );
+        dir
+    }
+
+    async fn conn() -> SqliteConnection {
+        SqliteConnection::connect("sqlite::memory:").await.unwrap()
+    }
+
+    fn states(statuses: &[MigrationStatus]) -> Vec<(i64, MigrationState)> {
+        statuses.iter().map(|s| (s.version, s.state)).collect()
+    }
+
+    async fn has_table(conn: &mut SqliteConnection, table: &str) -> bool {
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = ?)")
+            .bind(table)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn up_down_and_status() {
+        let dir = fixture();
+        let migrations = Migrations::load(dir.path()).await.unwrap();
+        let mut conn = conn().await;
+
+        assert_eq!(
+            states(&migrations.status(&mut conn).await.unwrap()),
+            [(1, MigrationState::Pending), (2, MigrationState::Pending)]
+        );
+
+        let steps = migrations.up(&mut conn, Some(1), false).await.unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(
+            states(&migrations.status(&mut conn).await.unwrap()),
+            [(1, MigrationState::Applied), (2, MigrationState::Pending)]
+        );
+
+        migrations.up(&mut conn, None, false).await.unwrap();
+        let reverted = migrations.down(&mut conn, 2, false).await.unwrap();
+        assert_eq!(
+            reverted.iter().map(|s| s.version).collect::<Vec<_>>(),
+            [2, 1]
+        );
+        assert!(!has_table(&mut conn, "artists").await);
+    }
+
+    #[tokio::test]
+    async fn dry_run_only_prints() {
+        let dir = fixture();
+        let migrations = Migrations::load(dir.path()).await.unwrap();
+        let mut conn = conn().await;
+
+        let steps = migrations.up(&mut conn, None, true).await.unwrap();
+        assert_eq!(
+            steps[0].sql,
+            "CREATE TABLE artists (id INTEGER PRIMARY KEY, name TEXT NOT NULL);"
+        );
+        assert_eq!(steps[0].direction, Direction::Up);
+        assert!(!has_table(&mut conn, "artists").await);
+
+        migrations.up(&mut conn, None, false).await.unwrap();
+        let steps = migrations.down(&mut conn, 1, true).await.unwrap();
+        assert_eq!(steps[0].sql, "ALTER TABLE artists DROP COLUMN country;");
+        assert_eq!(
+            states(&migrations.status(&mut conn).await.unwrap()),
+            [(1, MigrationState::Applied), (2, MigrationState::Applied)]
+        );
+    }
+
+    #[tokio::test]
+    async fn status_and_dry_runs_work_read_only() {
+        let dir = fixture();
+        let db = dir.path().join("songs.db");
+        let options = SqliteConnectOptions::new().filename(&db);
+        SqliteConnection::connect_with(&options.clone().create_if_missing(true))
+            .await
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+
+        let migrations = Migrations::load(dir.path()).await.unwrap();
+        let mut conn = SqliteConnection::connect_with(&options.read_only(true))
+            .await
+            .unwrap();
+        assert_eq!(
+            states(&migrations.status(&mut conn).await.unwrap()),
+            [(1, MigrationState::Pending), (2, MigrationState::Pending)]
+        );
+        assert_eq!(migrations.up(&mut conn, None, true).await.unwrap().len(), 2);
+        assert!(
+            migrations
+                .down(&mut conn, 1, true)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(!has_table(&mut conn, "_sqlx_migrations").await);
+    }
+
+    #[tokio::test]
+    async fn refuses_edited_migrations() {
+        let dir = fixture();
+        let mut conn = conn().await;
+        Migrations::load(dir.path())
+            .await
+            .unwrap()
+            .up(&mut conn, Some(1), false)
+            .await
+            .unwrap();
+
+        write(
+            dir.path(),
+            "1_create_artists.up.sql",
+            "CREATE TABLE artists (id INTEGER PRIMARY KEY, name TEXT);",
+        );
+        let migrations = Migrations::load(dir.path()).await.unwrap();
+
+        assert_eq!(
+            states(&migrations.status(&mut conn).await.unwrap()),
+            [(1, MigrationState::Modified), (2, MigrationState::Pending)]
+        );
+        let err = migrations.up(&mut conn, None, false).await.unwrap_err();
+        assert_eq!(err.kind(), CustomErrorKind::InvalidData);
+        assert!(err.to_string().contains("edited afterwards: 1"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn songs_migrations_round_trip() {
+        let migrations = Migrations::load(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+            .await
+            .unwrap();
+        let mut conn = conn().await;
+
+        migrations.up(&mut conn, None, false).await.unwrap();
+        assert!(has_table(&mut conn, "songs").await);
+        let all = migrations.status(&mut conn).await.unwrap().len();
+        migrations.down(&mut conn, all, false).await.unwrap();
+        assert!(!has_table(&mut conn, "songs").await);
+    }
+
+    #[test]
+    fn creates_reversible_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let [up, down] = new_migration(dir.path(), "Add Albums").unwrap();
+
+        let up = up.file_name().unwrap().to_str().unwrap().to_owned();
+        let down = down.file_name().unwrap().to_str().unwrap().to_owned();
+        assert!(up.ends_with("_add_albums.up.sql"), "{up}");
+        assert_eq!(up.replace(".up.", ".down."), down);
+        assert_eq!(up.len(), "20250101000000_add_albums.up.sql".len());
+
+        assert_eq!(
+            new_migration(dir.path(), " ").unwrap_err().kind(),
+            CustomErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        let at = |secs| timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "19700101000000");
+        assert_eq!(at(1_759_068_763), "20250928141243");
+        assert_eq!(at(951_782_400), "20000229000000");
+    }
+}
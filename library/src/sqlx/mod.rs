@@ -1,3 +1,10 @@
+pub mod changes;
+pub mod fts;
+pub mod jobs;
+pub mod migrate;
+pub mod repository;
+pub mod transaction;
+
 #[cfg(test)]
 mod tests {
     use sqlx::sqlite::SqlitePoolOptions;
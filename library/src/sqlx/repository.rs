@@ -0,0 +1,478 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+
+use super::fts::FtsQuery;
+use crate::errors::{CustomError, CustomErrorKind};
+
+/// A row in the `songs` table.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct Song {
+    pub id: i64,
+    pub artist: String,
+    pub title: String,
+}
+
+/// The fields a caller gets to set on a song.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewSong {
+    pub artist: String,
+    pub title: String,
+}
+
+/// Keyset pagination: songs with an id after `after`, oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub after: Option<i64>,
+    pub limit: u32,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            after: None,
+            limit: 50,
+        }
+    }
+}
+
+/// Substring filters, both case-insensitive. `None` matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongFilter {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+/// A full-text match, best first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub song: Song,
+    /// BM25 score, lower is better.
+    pub rank: f64,
+    /// The best matching part of the song as HTML: escaped, with matches wrapped in `<mark>`.
+    pub snippet: String,
+}
+
+// snippet() marks matches with these, since the text around them still has to be escaped
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[async_trait]
+pub trait SongRepository: Send + Sync {
+    async fn create(&self, song: &NewSong) -> Result<Song, CustomError>;
+    async fn get(&self, id: i64) -> Result<Song, CustomError>;
+    async fn list(&self, page: Page) -> Result<Vec<Song>, CustomError>;
+    async fn search(&self, filter: &SongFilter, page: Page) -> Result<Vec<Song>, CustomError>;
+    async fn search_text(
+        &self,
+        query: &FtsQuery,
+        limit: u32,
+    ) -> Result<Vec<SearchHit>, CustomError>;
+    async fn update(&self, id: i64, song: &NewSong) -> Result<Song, CustomError>;
+    async fn delete(&self, id: i64) -> Result<(), CustomError>;
+}
+
+/// [`SongRepository`] over a sqlite pool that has had `./migrations` run on it.
+///
+/// The queries are checked against the schema at compile time. After changing one, regenerate
+/// `.sqlx` with `cargo sqlx prepare` so the crate still builds without a database.
+#[derive(Debug, Clone)]
+pub struct SqliteSongRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteSongRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+fn not_found(id: i64) -> CustomError {
+    CustomError::new(CustomErrorKind::NotFound, format!("no song with id {id}"))
+}
+
+// turns a substring into a LIKE pattern, so `%` and `_` in user input match literally
+fn like_pattern(needle: &Option<String>) -> Option<String> {
+    needle.as_ref().map(|needle| {
+        let escaped = needle
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    })
+}
+
+#[async_trait]
+impl SongRepository for SqliteSongRepository {
+    async fn create(&self, song: &NewSong) -> Result<Song, CustomError> {
+        let song = sqlx::query_as!(
+            Song,
+            r#"INSERT INTO songs (artist, title) VALUES (?, ?)
+            RETURNING id as "id!", artist, title"#,
+            song.artist,
+            song.title
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(song)
+    }
+
+    async fn get(&self, id: i64) -> Result<Song, CustomError> {
+        sqlx::query_as!(Song, "SELECT id, artist, title FROM songs WHERE id = ?", id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| not_found(id))
+    }
+
+    async fn list(&self, page: Page) -> Result<Vec<Song>, CustomError> {
+        let after = page.after.unwrap_or(i64::MIN);
+        let songs = sqlx::query_as!(
+            Song,
+            "SELECT id, artist, title FROM songs WHERE id > ? ORDER BY id LIMIT ?",
+            after,
+            page.limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(songs)
+    }
+
+    async fn search(&self, filter: &SongFilter, page: Page) -> Result<Vec<Song>, CustomError> {
+        let artist = like_pattern(&filter.artist);
+        let title = like_pattern(&filter.title);
+        let after = page.after.unwrap_or(i64::MIN);
+        let songs = sqlx::query_as!(
+            Song,
+            r#"SELECT id, artist, title FROM songs
+            WHERE (?1 IS NULL OR artist LIKE ?1 ESCAPE '\')
+              AND (?2 IS NULL OR title LIKE ?2 ESCAPE '\')
+              AND id > ?3
+            ORDER BY id
+            LIMIT ?4"#,
+            artist,
+            title,
+            after,
+            page.limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(songs)
+    }
+
+    async fn search_text(
+        &self,
+        query: &FtsQuery,
+        limit: u32,
+    ) -> Result<Vec<SearchHit>, CustomError> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = query.to_string();
+        let rows = sqlx::query!(
+            r#"SELECT songs.id as "id!", songs.artist, songs.title,
+                bm25(songs_fts) as "rank!: f64",
+                snippet(songs_fts, -1, char(57344), char(57345), '…', 10) as "snippet!: String"
+            FROM songs_fts
+            JOIN songs ON songs.id = songs_fts.rowid
+            WHERE songs_fts MATCH ?
+            ORDER BY rank
+            LIMIT ?"#,
+            query,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| SearchHit {
+                song: Song {
+                    id: row.id,
+                    artist: row.artist,
+                    title: row.title,
+                },
+                rank: row.rank,
+                snippet: highlight(&row.snippet),
+            })
+            .collect())
+    }
+
+    async fn update(&self, id: i64, song: &NewSong) -> Result<Song, CustomError> {
+        sqlx::query_as!(
+            Song,
+            r#"UPDATE songs SET artist = ?, title = ? WHERE id = ?
+            RETURNING id as "id!", artist, title"#,
+            song.artist,
+            song.title,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| not_found(id))
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), CustomError> {
+        let result = sqlx::query!("DELETE FROM songs WHERE id = ?", id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // a plain in-memory database is per connection, so keep exactly one around
+    async fn repo() -> SqliteSongRepository {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        SqliteSongRepository::new(pool)
+    }
+
+    fn song(artist: &str, title: &str) -> NewSong {
+        NewSong {
+            artist: artist.to_owned(),
+            title: title.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn crud() {
+        let repo = repo().await;
+
+        let lemon = repo.create(&song("Kenshi Yonezu", "Lemon")).await.unwrap();
+        assert_eq!(repo.get(lemon.id).await.unwrap(), lemon);
+
+        let updated = repo
+            .update(lemon.id, &song("Kenshi Yonezu", "Kick Back"))
+            .await
+            .unwrap();
+        assert_eq!(updated.title, "Kick Back");
+        assert_eq!(repo.get(lemon.id).await.unwrap(), updated);
+
+        repo.delete(lemon.id).await.unwrap();
+        assert_eq!(
+            repo.get(lemon.id).await.unwrap_err().kind(),
+            CustomErrorKind::NotFound
+        );
+        assert_eq!(
+            repo.delete(lemon.id).await.unwrap_err().kind(),
+            CustomErrorKind::NotFound
+        );
+        assert_eq!(
+            repo.update(lemon.id, &song("a", "b"))
+                .await
+                .unwrap_err()
+                .kind(),
+            CustomErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn paginates_by_id() {
+        let repo = repo().await;
+        for i in 0..5 {
+            repo.create(&song("artist", &format!("song {i}")))
+                .await
+                .unwrap();
+        }
+
+        let first = repo
+            .list(Page {
+                after: None,
+                limit: 2,
+            })
+            .await
+            .unwrap();
+        let second = repo
+            .list(Page {
+                after: Some(first[1].id),
+                limit: 2,
+            })
+            .await
+            .unwrap();
+        let last = repo
+            .list(Page {
+                after: Some(second[1].id),
+                limit: 2,
+            })
+            .await
+            .unwrap();
+
+        let titles = |songs: &[Song]| songs.iter().map(|s| s.title.clone()).collect::<Vec<_>>();
+        assert_eq!(titles(&first), ["song 0", "song 1"]);
+        assert_eq!(titles(&second), ["song 2", "song 3"]);
+        assert_eq!(titles(&last), ["song 4"]);
+    }
+
+    #[tokio::test]
+    async fn searches_by_artist_and_title() {
+        let repo = repo().await;
+        repo.create(&song("Kenshi Yonezu", "Lemon")).await.unwrap();
+        repo.create(&song("Kenshi Yonezu", "Kick Back"))
+            .await
+            .unwrap();
+        repo.create(&song("YOASOBI", "Idol")).await.unwrap();
+        repo.create(&song("100%", "Literal")).await.unwrap();
+
+        let search = |artist: Option<&str>, title: Option<&str>| {
+            let repo = repo.clone();
+            let filter = SongFilter {
+                artist: artist.map(str::to_owned),
+                title: title.map(str::to_owned),
+            };
+            async move {
+                repo.search(&filter, Page::default())
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|s| s.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(search(Some("kenshi"), None).await, ["Lemon", "Kick Back"]);
+        assert_eq!(search(Some("kenshi"), Some("back")).await, ["Kick Back"]);
+        assert_eq!(
+            search(None, Some("i")).await,
+            ["Kick Back", "Idol", "Literal"]
+        );
+        // wildcards in the input are matched literally
+        assert_eq!(search(Some("%"), None).await, ["Literal"]);
+        assert_eq!(search(None, None).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn full_text_search_ranks_and_highlights() {
+        let repo = repo().await;
+        repo.create(&song("Kenshi Yonezu", "Lemon")).await.unwrap();
+        repo.create(&song("Kenshi Yonezu", "Kick Back"))
+            .await
+            .unwrap();
+        repo.create(&song("Lemon Demon", "Two Trucks"))
+            .await
+            .unwrap();
+        repo.create(&song("Beyoncé", "Lemonade")).await.unwrap();
+
+        let hits = repo.search_text(&FtsQuery::new("lemo"), 10).await.unwrap();
+        let mut ranks = hits.iter().map(|hit| hit.rank).collect::<Vec<_>>();
+        ranks.sort_by(f64::total_cmp);
+        assert_eq!(hits.iter().map(|hit| hit.rank).collect::<Vec<_>>(), ranks);
+        let mut snippets = hits.into_iter().map(|hit| hit.snippet).collect::<Vec<_>>();
+        snippets.sort();
+        assert_eq!(
+            snippets,
+            [
+                "<mark>Lemon</mark>",
+                "<mark>Lemon</mark> Demon",
+                "<mark>Lemonade</mark>"
+            ]
+        );
+
+        let hits = repo
+            .search_text(&FtsQuery::new("kens").title("back"), 10)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].song.title, "Kick Back");
+        // the snippet comes from whichever column matched best
+        assert_eq!(hits[0].snippet, "<mark>Kenshi</mark> Yonezu");
+
+        // diacritics are folded
+        let hits = repo
+            .search_text(&FtsQuery::new("beyonce"), 10)
+            .await
+            .unwrap();
+        assert_eq!(hits[0].song.artist, "Beyoncé");
+
+        let limited = repo.search_text(&FtsQuery::new("lemon"), 1).await.unwrap();
+        assert_eq!(limited.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn snippets_escape_the_song_text() {
+        let repo = repo().await;
+        repo.create(&song("Mallory & co", "<script>alert('lemon')</script>"))
+            .await
+            .unwrap();
+
+        let hits = repo.search_text(&FtsQuery::new("lemon"), 10).await.unwrap();
+        assert_eq!(
+            hits[0].snippet,
+            "&lt;script&gt;alert(&#39;<mark>lemon</mark>&#39;)&lt;/script&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn full_text_index_follows_updates_and_deletes() {
+        let repo = repo().await;
+        let lemon = repo.create(&song("Kenshi Yonezu", "Lemon")).await.unwrap();
+        let titles = |query: &'static str| {
+            let repo = repo.clone();
+            async move {
+                repo.search_text(&FtsQuery::new(query), 10)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|hit| hit.song.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        repo.update(lemon.id, &song("Kenshi Yonezu", "Kick Back"))
+            .await
+            .unwrap();
+        assert!(titles("lemon").await.is_empty());
+        assert_eq!(titles("kick").await, ["Kick Back"]);
+
+        repo.delete(lemon.id).await.unwrap();
+        assert!(titles("kick").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_text_search_treats_syntax_as_text() {
+        let repo = repo().await;
+        repo.create(&song("AC/DC", "T.N.T.")).await.unwrap();
+        repo.create(&song("Nine Inch Nails", "Head Like a Hole"))
+            .await
+            .unwrap();
+
+        for input in [r#"""#, "NOT", "title:", "(", "*", "AND OR", "NEAR(a b)", ""] {
+            let hits = repo.search_text(&FtsQuery::new(input), 10).await;
+            assert!(hits.is_ok(), "{input}: {hits:?}");
+        }
+        // punctuation inside a word splits it into a phrase, like the tokenizer did to the song
+        let hits = repo.search_text(&FtsQuery::new("ac/dc"), 10).await.unwrap();
+        assert_eq!(hits[0].song.title, "T.N.T.");
+        let hits = repo.search_text(&FtsQuery::new("t.n.t"), 10).await.unwrap();
+        assert_eq!(hits[0].song.artist, "AC/DC");
+    }
+}
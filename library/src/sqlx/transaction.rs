@@ -0,0 +1,310 @@
+use std::{panic::AssertUnwindSafe, time::Duration};
+
+use futures::{FutureExt, future::BoxFuture};
+use sqlx::{Connection, Sqlite, SqliteConnection, SqlitePool, Transaction};
+
+use crate::errors::{CustomError, CustomErrorKind};
+
+/// Jittered exponential backoff. [`with_transaction_retry`] uses it when sqlite reports the
+/// database busy or locked, [`Worker`](super::jobs::Worker)s between attempts at a failing job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // exponential, with jitter so competing writers don't keep colliding in lockstep
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        exp / 2 + exp.mul_f64(fastrand::f64() / 2.0)
+    }
+}
+
+/// Runs `f` in a transaction with the default [`RetryPolicy`].
+///
+/// The closure has to box its future, since it borrows the transaction:
+///
+/// ```ignore
+/// with_transaction(&pool, |tx| Box::pin(async move {
+///     sqlx::query("INSERT INTO songs (artist, title) VALUES ('a', 'b')").execute(&mut *tx).await?;
+///     Ok(())
+/// }))
+/// ```
+pub async fn with_transaction<T, F>(pool: &SqlitePool, f: F) -> Result<T, CustomError>
+where
+    F: for<'c> FnMut(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, CustomError>>,
+{
+    with_transaction_retry(pool, RetryPolicy::default(), f).await
+}
+
+/// Commits if `f` returns `Ok`, rolls back if it returns `Err` or panics.
+///
+/// If anything fails with [`CustomErrorKind::ResourceBusy`], the whole transaction is rolled
+/// back and `f` runs again from the start, so it shouldn't have side effects outside the
+/// database.
+pub async fn with_transaction_retry<T, F>(
+    pool: &SqlitePool,
+    policy: RetryPolicy,
+    mut f: F,
+) -> Result<T, CustomError>
+where
+    F: for<'c> FnMut(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, CustomError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match attempt_transaction(pool, &mut f).await {
+            Err(e)
+                if e.kind() == CustomErrorKind::ResourceBusy && attempt < policy.max_attempts =>
+            {
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn attempt_transaction<T, F>(pool: &SqlitePool, f: &mut F) -> Result<T, CustomError>
+where
+    F: for<'c> FnMut(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, CustomError>>,
+{
+    let mut tx = pool.begin().await?;
+    let res = AssertUnwindSafe(f(&mut tx)).catch_unwind().await;
+    settle(tx, res).await
+}
+
+/// Runs `f` inside a savepoint on a connection that's already in a transaction. An `Err` or
+/// panic only undoes what `f` did, the outer transaction carries on.
+pub async fn with_savepoint<T, F>(conn: &mut SqliteConnection, f: F) -> Result<T, CustomError>
+where
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, CustomError>>,
+{
+    // begin on a connection that's already in a transaction issues SAVEPOINT instead
+    let mut savepoint = conn.begin().await?;
+    let res = AssertUnwindSafe(f(&mut savepoint)).catch_unwind().await;
+    settle(savepoint, res).await
+}
+
+async fn settle<T>(
+    tx: Transaction<'_, Sqlite>,
+    res: std::thread::Result<Result<T, CustomError>>,
+) -> Result<T, CustomError> {
+    match res {
+        Ok(Ok(value)) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Ok(Err(e)) => {
+            // a failed rollback would hide why we rolled back, so report the original error.
+            let _ = tx.rollback().await;
+            Err(e)
+        }
+        Err(panic) => {
+            let _ = tx.rollback().await;
+            std::panic::resume_unwind(panic)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::{
+        str::FromStr,
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+    };
+    use tokio::sync::Barrier;
+
+    async fn pool(name: &str, connections: u32) -> SqlitePool {
+        // every connection to a named shared-cache database sees the same data
+        let options =
+            SqliteConnectOptions::from_str(&format!("sqlite:file:{name}?mode=memory&cache=shared"))
+                .unwrap();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(connections)
+            .min_connections(connections)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    async fn insert(conn: &mut SqliteConnection, title: &str) -> Result<(), CustomError> {
+        sqlx::query("INSERT INTO songs (artist, title) VALUES ('artist', ?)")
+            .bind(title)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn titles(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT title FROM songs ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn commits_on_ok_and_rolls_back_on_err() {
+        let pool = pool("tx_commit_rollback", 1).await;
+
+        with_transaction(&pool, |tx| {
+            Box::pin(async move {
+                insert(tx, "one").await?;
+                insert(tx, "two").await
+            })
+        })
+        .await
+        .unwrap();
+
+        let err = with_transaction(&pool, |tx| {
+            Box::pin(async move {
+                insert(tx, "three").await?;
+                Err::<(), _>(CustomError::new(CustomErrorKind::InvalidArgument, "nope"))
+            })
+        })
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), CustomErrorKind::InvalidArgument);
+        assert_eq!(titles(&pool).await, ["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_on_panic() {
+        let pool = pool("tx_panic", 1).await;
+
+        let res = AssertUnwindSafe(with_transaction::<(), _>(&pool, |tx| {
+            Box::pin(async move {
+                insert(tx, "doomed").await?;
+                panic!("boom");
+            })
+        }))
+        .catch_unwind()
+        .await;
+
+        assert!(res.is_err());
+        assert!(titles(&pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn savepoints_only_undo_their_own_work() {
+        let pool = pool("tx_savepoints", 1).await;
+
+        with_transaction(&pool, |tx| {
+            Box::pin(async move {
+                insert(tx, "outer").await?;
+                with_savepoint(tx, |sp| Box::pin(async move { insert(sp, "kept").await })).await?;
+                let failed = with_savepoint(tx, |sp| {
+                    Box::pin(async move {
+                        insert(sp, "undone").await?;
+                        // savepoints nest as deep as you like
+                        with_savepoint(sp, |inner| {
+                            Box::pin(async move { insert(inner, "also undone").await })
+                        })
+                        .await?;
+                        Err::<(), _>(CustomError::new(CustomErrorKind::Other, "inner failure"))
+                    })
+                })
+                .await;
+                assert!(failed.is_err());
+                insert(tx, "after").await
+            })
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(titles(&pool).await, ["outer", "kept", "after"]);
+    }
+
+    // Two transactions that both read, then both write, deadlock on sqlite's table locks. One
+    // of them gets SQLITE_LOCKED and has to start over.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn retries_when_locked() {
+        let pool = pool("tx_locked", 2).await;
+        let barrier = Arc::new(Barrier::new(2));
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let transfer = |title: &'static str| {
+            let pool = pool.clone();
+            let barrier = barrier.clone();
+            let attempts = attempts.clone();
+            tokio::spawn(async move {
+                with_transaction(&pool, move |tx| {
+                    let barrier = barrier.clone();
+                    let first = attempts.fetch_add(1, Ordering::SeqCst) < 2;
+                    Box::pin(async move {
+                        let _: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM songs")
+                            .fetch_one(&mut *tx)
+                            .await?;
+                        // make sure both have read before either writes, the first time round
+                        if first {
+                            barrier.wait().await;
+                        }
+                        insert(tx, title).await
+                    })
+                })
+                .await
+            })
+        };
+
+        let (a, b) = tokio::join!(transfer("a"), transfer("b"));
+        a.unwrap().unwrap();
+        b.unwrap().unwrap();
+
+        assert!(attempts.load(Ordering::SeqCst) > 2);
+        let mut titles = titles(&pool).await;
+        titles.sort();
+        assert_eq!(titles, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let pool = pool("tx_give_up", 1).await;
+        let attempts = AtomicU32::new(0);
+
+        let err = with_transaction_retry(
+            &pool,
+            RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            },
+            |_| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async {
+                    Err::<(), _>(CustomError::new(CustomErrorKind::ResourceBusy, "busy"))
+                })
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), CustomErrorKind::ResourceBusy);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
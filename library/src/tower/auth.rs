@@ -0,0 +1,869 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderValue, Request, StatusCode, header, request::Parts};
+use http_body_util::LengthLimitError;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tower::{BoxError, Layer, Service};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const HMAC_KEY_ID_HEADER: &str = "x-auth-key-id";
+pub const HMAC_TIMESTAMP_HEADER: &str = "x-auth-timestamp";
+pub const HMAC_SIGNATURE_HEADER: &str = "x-auth-signature";
+
+/// Who made the request, as established by one of the [`Verifier`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub id: String,
+    pub scheme: AuthScheme,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    ApiKey,
+    Jwt,
+    Hmac,
+}
+
+impl AuthScheme {
+    /// What goes in `WWW-Authenticate` when a credential of this kind is missing or rejected.
+    fn challenge(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            AuthScheme::ApiKey => "ApiKey header=\"x-api-key\"",
+            AuthScheme::Jwt => "Bearer",
+            AuthScheme::Hmac => "HMAC-SHA256",
+        })
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("missing credentials")]
+    Missing,
+    #[error("invalid credentials: {0}")]
+    Invalid(String),
+    #[error("credentials expired")]
+    Expired,
+    #[error("request was already seen")]
+    Replayed,
+    #[error("request body too large to verify")]
+    BodyTooLarge,
+    #[error("couldn't read the request body")]
+    BadBody,
+}
+
+impl IntoResponse for AuthError {
+    /// Just the status and message, [`Auth`] adds the `WWW-Authenticate` challenges.
+    fn into_response(self) -> Response {
+        let status = match self {
+            AuthError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AuthError::BadBody => StatusCode::BAD_REQUEST,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+// challenges every scheme in `schemes` if the error is a 401
+fn reject(error: AuthError, schemes: impl IntoIterator<Item = AuthScheme>) -> Response {
+    let mut res = error.into_response();
+    if res.status() == StatusCode::UNAUTHORIZED {
+        let mut challenged = Vec::new();
+        for scheme in schemes {
+            if !challenged.contains(&scheme) {
+                challenged.push(scheme);
+                res.headers_mut()
+                    .append(header::WWW_AUTHENTICATE, scheme.challenge());
+            }
+        }
+    }
+    res
+}
+
+/// Checks one kind of credential.
+///
+/// Returns `Ok(None)` when the request doesn't carry this kind of credential at all, so the next
+/// verifier gets a go, and an error when it does but the credential is bad.
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    async fn verify(&self, parts: &Parts, body: &Bytes) -> Result<Option<Principal>, AuthError>;
+
+    /// The kind of credential it checks, used to pick the challenge when it's missing or bad.
+    fn scheme(&self) -> AuthScheme;
+
+    /// Whether the verifier looks at the body. The body is only buffered if one does.
+    fn needs_body(&self) -> bool {
+        false
+    }
+}
+
+/// Static API keys sent in `x-api-key`.
+pub struct ApiKeyVerifier {
+    // keys are stored hashed so every comparison is over the same number of bytes
+    keys: Vec<([u8; 32], String)>,
+}
+
+impl ApiKeyVerifier {
+    /// Takes `(key, principal id)` pairs.
+    pub fn new<K: AsRef<[u8]>, P: Into<String>>(keys: impl IntoIterator<Item = (K, P)>) -> Self {
+        let keys = keys
+            .into_iter()
+            .map(|(key, id)| (Sha256::digest(key.as_ref()).into(), id.into()))
+            .collect();
+        Self { keys }
+    }
+}
+
+#[async_trait]
+impl Verifier for ApiKeyVerifier {
+    async fn verify(&self, parts: &Parts, _body: &Bytes) -> Result<Option<Principal>, AuthError> {
+        let Some(presented) = parts.headers.get(API_KEY_HEADER) else {
+            return Ok(None);
+        };
+        let presented: [u8; 32] = Sha256::digest(presented.as_bytes()).into();
+
+        // check every key so the timing doesn't depend on which one matched
+        let mut found = None;
+        for (key, id) in &self.keys {
+            if bool::from(key.ct_eq(&presented)) {
+                found = Some(id);
+            }
+        }
+
+        match found {
+            Some(id) => Ok(Some(Principal {
+                id: id.clone(),
+                scheme: AuthScheme::ApiKey,
+            })),
+            None => Err(AuthError::Invalid("unknown api key".into())),
+        }
+    }
+
+    fn scheme(&self) -> AuthScheme {
+        AuthScheme::ApiKey
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+/// `Authorization: Bearer` JWTs signed with HS256 or EdDSA.
+pub struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    pub fn hs256(secret: &[u8], issuer: &str, audience: &str) -> Self {
+        Self::new(
+            DecodingKey::from_secret(secret),
+            Algorithm::HS256,
+            issuer,
+            audience,
+        )
+    }
+
+    /// `public_key` is the raw 32 byte Ed25519 public key.
+    pub fn eddsa(public_key: &[u8], issuer: &str, audience: &str) -> Self {
+        Self::new(
+            DecodingKey::from_ed_der(public_key),
+            Algorithm::EdDSA,
+            issuer,
+            audience,
+        )
+    }
+
+    fn new(key: DecodingKey, algorithm: Algorithm, issuer: &str, audience: &str) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        Self { key, validation }
+    }
+
+    /// How much clock skew to allow when checking `exp` and `nbf`. Defaults to 60 seconds.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway.as_secs();
+        self
+    }
+}
+
+#[async_trait]
+impl Verifier for JwtVerifier {
+    async fn verify(&self, parts: &Parts, _body: &Bytes) -> Result<Option<Principal>, AuthError> {
+        let Some(token) = bearer_token(&parts.headers) else {
+            return Ok(None);
+        };
+
+        let data = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation).map_err(
+            |e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::Expired,
+                _ => AuthError::Invalid(e.to_string()),
+            },
+        )?;
+
+        Ok(Some(Principal {
+            id: data.claims.sub,
+            scheme: AuthScheme::Jwt,
+        }))
+    }
+
+    fn scheme(&self) -> AuthScheme {
+        AuthScheme::Jwt
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Requests signed with HMAC-SHA256 over the method, path, timestamp and body.
+///
+/// Signatures are only accepted within `window` of their timestamp, and each one only once, so
+/// a captured request can't be replayed.
+pub struct HmacVerifier {
+    secrets: HashMap<String, Vec<u8>>,
+    window: Duration,
+    // signature -> when it stops being valid anyway and can be forgotten
+    seen: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl HmacVerifier {
+    /// Takes `(key id, secret)` pairs. The key id becomes the principal id.
+    pub fn new<K: Into<String>, S: Into<Vec<u8>>>(
+        secrets: impl IntoIterator<Item = (K, S)>,
+        window: Duration,
+    ) -> Self {
+        Self {
+            secrets: secrets
+                .into_iter()
+                .map(|(id, secret)| (id.into(), secret.into()))
+                .collect(),
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Signs a request, for clients and tests. Returns the hex encoded signature.
+    pub fn sign(secret: &[u8], method: &str, path: &str, timestamp: u64, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+        mac.update(&string_to_sign(method, path, timestamp, body));
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn remember(&self, signature: &[u8], timestamp: u64, now: u64) -> Result<(), AuthError> {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expires| *expires > now);
+        if seen.contains_key(signature) {
+            return Err(AuthError::Replayed);
+        }
+        seen.insert(signature.to_vec(), timestamp + self.window.as_secs());
+        Ok(())
+    }
+}
+
+fn string_to_sign(method: &str, path: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    format!(
+        "{method}\n{path}\n{timestamp}\n{}",
+        hex::encode(Sha256::digest(body))
+    )
+    .into_bytes()
+}
+
+#[async_trait]
+impl Verifier for HmacVerifier {
+    async fn verify(&self, parts: &Parts, body: &Bytes) -> Result<Option<Principal>, AuthError> {
+        let header = |name| parts.headers.get(name).and_then(|v| v.to_str().ok());
+        let Some(signature) = header(HMAC_SIGNATURE_HEADER) else {
+            return Ok(None);
+        };
+        let key_id = header(HMAC_KEY_ID_HEADER).ok_or(AuthError::Missing)?;
+        let timestamp: u64 = header(HMAC_TIMESTAMP_HEADER)
+            .ok_or(AuthError::Missing)?
+            .parse()
+            .map_err(|_| AuthError::Invalid("bad timestamp".into()))?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if now.abs_diff(timestamp) > self.window.as_secs() {
+            return Err(AuthError::Expired);
+        }
+
+        let secret = self
+            .secrets
+            .get(key_id)
+            .ok_or_else(|| AuthError::Invalid("unknown key id".into()))?;
+        let signature =
+            hex::decode(signature).map_err(|_| AuthError::Invalid("bad signature".into()))?;
+
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+        mac.update(&string_to_sign(
+            parts.method.as_str(),
+            path,
+            timestamp,
+            body,
+        ));
+        // verify_slice compares in constant time
+        mac.verify_slice(&signature)
+            .map_err(|_| AuthError::Invalid("signature mismatch".into()))?;
+
+        self.remember(&signature, timestamp, now)?;
+        Ok(Some(Principal {
+            id: key_id.to_owned(),
+            scheme: AuthScheme::Hmac,
+        }))
+    }
+
+    fn scheme(&self) -> AuthScheme {
+        AuthScheme::Hmac
+    }
+
+    fn needs_body(&self) -> bool {
+        true
+    }
+}
+
+/// Rejects requests that none of its verifiers accept, and puts the [`Principal`] in the
+/// request extensions for the ones that pass.
+#[derive(Clone, Default)]
+pub struct AuthLayer {
+    verifiers: Vec<Arc<dyn Verifier>>,
+    max_body_bytes: Option<usize>,
+}
+
+impl AuthLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a verifier. They're tried in the order they were added.
+    pub fn verifier(mut self, verifier: impl Verifier + 'static) -> Self {
+        self.verifiers.push(Arc::new(verifier));
+        self
+    }
+
+    /// The most body that will be buffered for verifiers that sign it. Defaults to 1MiB.
+    pub fn max_body_bytes(mut self, max: usize) -> Self {
+        self.max_body_bytes = Some(max);
+        self
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            verifiers: self.verifiers.clone().into(),
+            max_body_bytes: self.max_body_bytes.unwrap_or(1024 * 1024),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    verifiers: Arc<[Arc<dyn Verifier>]>,
+    max_body_bytes: usize,
+}
+
+impl<S> Auth<S> {
+    async fn authenticate(
+        verifiers: &[Arc<dyn Verifier>],
+        parts: &Parts,
+        body: &Bytes,
+    ) -> Result<Principal, Response> {
+        for verifier in verifiers {
+            match verifier.verify(parts, body).await {
+                Ok(Some(principal)) => return Ok(principal),
+                Ok(None) => {}
+                Err(e) => return Err(reject(e, [verifier.scheme()])),
+            }
+        }
+        // nothing was presented, so offer everything that would have been accepted
+        Err(reject(
+            AuthError::Missing,
+            verifiers.iter().map(|v| v.scheme()),
+        ))
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Auth<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: HttpBody<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // the clone might not be ready, so keep the one poll_ready was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let verifiers = self.verifiers.clone();
+        let max_body_bytes = self.max_body_bytes;
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = Body::new(body);
+
+            let (mut parts, body, result) = if verifiers.iter().any(|v| v.needs_body()) {
+                let bytes = match axum::body::to_bytes(body, max_body_bytes).await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        // the client going away or the body stream failing isn't about its size
+                        let error = if e.into_inner().is::<LengthLimitError>() {
+                            AuthError::BodyTooLarge
+                        } else {
+                            AuthError::BadBody
+                        };
+                        return Ok(error.into_response());
+                    }
+                };
+                let result = Self::authenticate(&verifiers, &parts, &bytes).await;
+                (parts, Body::from(bytes), result)
+            } else {
+                let result = Self::authenticate(&verifiers, &parts, &Bytes::new()).await;
+                (parts, body, result)
+            };
+
+            match result {
+                Ok(principal) => {
+                    parts.extensions.insert(principal);
+                    inner.call(Request::from_parts(parts, body)).await
+                }
+                Err(res) => Ok(res),
+            }
+        })
+    }
+}
+
+/// Feeds the authenticated principal's id to a service keyed by client, like
+/// [`MultiRateLimiter`](crate::tower::rate_limit::MultiRateLimiter).
+#[derive(Clone)]
+pub struct KeyByPrincipal<S> {
+    inner: S,
+}
+
+impl<S> KeyByPrincipal<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, B> Service<Request<B>> for KeyByPrincipal<S>
+where
+    S: Service<(String, Request<B>)>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let key = req
+            .extensions()
+            .get::<Principal>()
+            .map(|p| p.id.clone())
+            .unwrap_or_else(|| "anonymous".to_owned());
+        self.inner.call((key, req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tower::rate_limit::MultiRateLimiter;
+    use axum::{Extension, Router, routing::post};
+    use jsonwebtoken::{EncodingKey, Header};
+    use pretty_assertions::assert_eq;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde::Serialize;
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    const ISSUER: &str = "https://auth.example.com";
+    const AUDIENCE: &str = "songs-api";
+
+    fn app(layer: AuthLayer) -> Router {
+        Router::new()
+            .route(
+                "/whoami",
+                post(|Extension(p): Extension<Principal>| async move { p.id }),
+            )
+            .layer(layer)
+    }
+
+    async fn call(app: Router, req: Request<Body>) -> (StatusCode, String) {
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn whoami() -> http::request::Builder {
+        Request::builder().method("POST").uri("/whoami")
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[tokio::test]
+    async fn api_keys() {
+        let layer = AuthLayer::new().verifier(ApiKeyVerifier::new([("s3cret", "alice")]));
+
+        let req = whoami()
+            .header(API_KEY_HEADER, "s3cret")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            call(app(layer.clone()), req).await,
+            (StatusCode::OK, "alice".into())
+        );
+
+        let req = whoami()
+            .header(API_KEY_HEADER, "guess")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            call(app(layer.clone()), req).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let req = whoami().body(Body::empty()).unwrap();
+        assert_eq!(
+            call(app(layer), req).await,
+            (StatusCode::UNAUTHORIZED, "missing credentials".into())
+        );
+    }
+
+    #[derive(Serialize)]
+    struct TestClaims<'a> {
+        sub: &'a str,
+        iss: &'a str,
+        aud: &'a str,
+        exp: u64,
+    }
+
+    fn bearer(token: &str) -> Request<Body> {
+        whoami()
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hs256_jwts() {
+        let secret = b"jwt-secret";
+        let layer = AuthLayer::new()
+            .verifier(JwtVerifier::hs256(secret, ISSUER, AUDIENCE).leeway(Duration::ZERO));
+        let sign = |claims: &TestClaims| {
+            jsonwebtoken::encode(
+                &Header::default(),
+                claims,
+                &EncodingKey::from_secret(secret),
+            )
+            .unwrap()
+        };
+
+        let valid = TestClaims {
+            sub: "bob",
+            iss: ISSUER,
+            aud: AUDIENCE,
+            exp: now() + 60,
+        };
+        assert_eq!(
+            call(app(layer.clone()), bearer(&sign(&valid))).await,
+            (StatusCode::OK, "bob".into())
+        );
+
+        let expired = TestClaims {
+            exp: now() - 60,
+            ..valid
+        };
+        assert_eq!(
+            call(app(layer.clone()), bearer(&sign(&expired))).await,
+            (StatusCode::UNAUTHORIZED, "credentials expired".into())
+        );
+
+        let wrong_audience = TestClaims {
+            aud: "someone-else",
+            ..valid
+        };
+        assert_eq!(
+            call(app(layer.clone()), bearer(&sign(&wrong_audience)))
+                .await
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let wrong_issuer = TestClaims {
+            iss: "https://evil.example.com",
+            ..valid
+        };
+        assert_eq!(
+            call(app(layer), bearer(&sign(&wrong_issuer))).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn eddsa_jwts() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let layer = AuthLayer::new().verifier(JwtVerifier::eddsa(
+            pair.public_key().as_ref(),
+            ISSUER,
+            AUDIENCE,
+        ));
+
+        let claims = TestClaims {
+            sub: "carol",
+            iss: ISSUER,
+            aud: AUDIENCE,
+            exp: now() + 60,
+        };
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::EdDSA),
+            &claims,
+            &EncodingKey::from_ed_der(pkcs8.as_ref()),
+        )
+        .unwrap();
+        assert_eq!(
+            call(app(layer.clone()), bearer(&token)).await,
+            (StatusCode::OK, "carol".into())
+        );
+
+        // an HS256 token can't be passed off as EdDSA
+        let forged = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(pair.public_key().as_ref()),
+        )
+        .unwrap();
+        assert_eq!(
+            call(app(layer), bearer(&forged)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    fn signed(key_id: &str, secret: &[u8], timestamp: u64, body: &'static str) -> Request<Body> {
+        let signature = HmacVerifier::sign(secret, "POST", "/whoami", timestamp, body.as_bytes());
+        whoami()
+            .header(HMAC_KEY_ID_HEADER, key_id)
+            .header(HMAC_TIMESTAMP_HEADER, timestamp)
+            .header(HMAC_SIGNATURE_HEADER, signature)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hmac_signed_requests() {
+        let secret = b"shared-secret";
+        let layer = AuthLayer::new().verifier(HmacVerifier::new(
+            [("service-a", secret.to_vec())],
+            Duration::from_secs(300),
+        ));
+
+        let ts = now();
+        assert_eq!(
+            call(app(layer.clone()), signed("service-a", secret, ts, "{}")).await,
+            (StatusCode::OK, "service-a".into())
+        );
+
+        // the exact same request again is a replay
+        assert_eq!(
+            call(app(layer.clone()), signed("service-a", secret, ts, "{}")).await,
+            (StatusCode::UNAUTHORIZED, "request was already seen".into())
+        );
+
+        // so is one signed long ago
+        assert_eq!(
+            call(
+                app(layer.clone()),
+                signed("service-a", secret, ts - 3600, "{}")
+            )
+            .await
+            .0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // tampering with the body breaks the signature
+        let mut req = signed("service-a", secret, ts + 1, "{}");
+        *req.body_mut() = Body::from("{\"admin\":true}");
+        assert_eq!(
+            call(app(layer.clone()), req).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        assert_eq!(
+            call(app(layer), signed("service-a", b"wrong", ts + 2, "{}"))
+                .await
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn verifiers_are_tried_in_order() {
+        let layer = AuthLayer::new()
+            .verifier(JwtVerifier::hs256(b"jwt-secret", ISSUER, AUDIENCE))
+            .verifier(ApiKeyVerifier::new([("s3cret", "alice")]));
+
+        let req = whoami()
+            .header(API_KEY_HEADER, "s3cret")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            call(app(layer), req).await,
+            (StatusCode::OK, "alice".into())
+        );
+    }
+
+    fn challenges(res: &Response) -> Vec<&str> {
+        res.headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn challenges_with_the_rejected_scheme() {
+        let secret = b"shared-secret";
+        let layer = AuthLayer::new()
+            .verifier(JwtVerifier::hs256(b"jwt-secret", ISSUER, AUDIENCE))
+            .verifier(ApiKeyVerifier::new([("s3cret", "alice")]))
+            .verifier(HmacVerifier::new(
+                [("service-a", secret.to_vec())],
+                Duration::from_secs(300),
+            ));
+
+        let res = app(layer.clone())
+            .oneshot(signed("service-a", b"wrong", now(), "{}"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenges(&res), ["HMAC-SHA256"]);
+
+        let req = whoami()
+            .header(API_KEY_HEADER, "guess")
+            .body(Body::empty())
+            .unwrap();
+        let res = app(layer.clone()).oneshot(req).await.unwrap();
+        assert_eq!(challenges(&res), ["ApiKey header=\"x-api-key\""]);
+
+        // with nothing presented, every scheme is on offer
+        let res = app(layer)
+            .oneshot(whoami().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            challenges(&res),
+            ["Bearer", "ApiKey header=\"x-api-key\"", "HMAC-SHA256"]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_oversized_bodies_are_413() {
+        let layer = AuthLayer::new()
+            .verifier(HmacVerifier::new(
+                [("service-a", b"shared-secret".to_vec())],
+                Duration::from_secs(300),
+            ))
+            .max_body_bytes(4);
+
+        let req = whoami().body(Body::from("too long")).unwrap();
+        assert_eq!(
+            call(app(layer.clone()), req).await.0,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let broken = futures::stream::iter([
+            Ok(Bytes::from("{")),
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+        ]);
+        let req = whoami().body(Body::from_stream(broken)).unwrap();
+        assert_eq!(
+            call(app(layer), req).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "couldn't read the request body".into()
+            )
+        );
+    }
+
+    // the principal can key the rate limiter, so each caller gets their own bucket
+    #[tokio::test(start_paused = true)]
+    async fn principal_keys_rate_limiter() {
+        let echo = service_fn(|req: Request<Body>| async move {
+            let id = req.extensions().get::<Principal>().unwrap().id.clone();
+            Ok::<_, ()>(id.into_response())
+        });
+        let limited = KeyByPrincipal::new(MultiRateLimiter::new(echo, 1, Duration::from_secs(1)));
+        let svc = ServiceBuilder::new()
+            .layer(AuthLayer::new().verifier(ApiKeyVerifier::new([("a-key", "a"), ("b-key", "b")])))
+            .service(limited);
+
+        let with_key = |key| {
+            whoami()
+                .header(API_KEY_HEADER, key)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert!(svc.clone().oneshot(with_key("a-key")).await.is_ok());
+        assert_eq!(
+            svc.clone().oneshot(with_key("a-key")).await.unwrap_err(),
+            "rate limited"
+        );
+        assert!(svc.oneshot(with_key("b-key")).await.is_ok());
+    }
+}
@@ -0,0 +1,185 @@
+use tower::Layer;
+use tower_http::{
+    decompression::{RequestDecompression, RequestDecompressionLayer},
+    limit::{RequestBodyLimit, RequestBodyLimitLayer},
+};
+
+/// Size limits for request bodies.
+///
+/// Compressed bodies are checked twice: once on the wire, where a `Content-Length` over the
+/// limit gets a 413 before any of the body is read, and once after decompression, so a small
+/// gzip bomb can't expand into gigabytes in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    /// The largest body accepted as sent by the client.
+    pub max_body_bytes: usize,
+    /// The largest body accepted after undoing `Content-Encoding`.
+    pub max_decompressed_bytes: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 2 * 1024 * 1024,
+            max_decompressed_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl BodyLimits {
+    pub fn layer(self) -> BodyLimitsLayer {
+        BodyLimitsLayer { limits: self }
+    }
+}
+
+/// Decompresses gzip, deflate, brotli and zstd request bodies while enforcing [`BodyLimits`].
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimitsLayer {
+    limits: BodyLimits,
+}
+
+impl<S> Layer<S> for BodyLimitsLayer {
+    type Service = RequestBodyLimit<RequestDecompression<RequestBodyLimit<S>>>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        // innermost first: the decompressed limit has to sit behind the decompression layer
+        let inner = RequestBodyLimitLayer::new(self.limits.max_decompressed_bytes).layer(inner);
+        let inner = RequestDecompressionLayer::new().layer(inner);
+        RequestBodyLimitLayer::new(self.limits.max_body_bytes).layer(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZlibEncoder, ZstdEncoder};
+    use axum::{
+        Router,
+        body::{Body, Bytes},
+        http::{Request, StatusCode, header},
+        routing::post,
+    };
+    use pretty_assertions::assert_eq;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::io::{AsyncRead, AsyncReadExt};
+    use tower::ServiceExt;
+
+    fn app(limits: BodyLimits) -> Router {
+        Router::new()
+            .route("/echo", post(|body: Bytes| async move { body }))
+            .layer(limits.layer())
+    }
+
+    async fn encode(mut encoder: impl AsyncRead + Unpin) -> Vec<u8> {
+        let mut out = Vec::new();
+        encoder.read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    async fn compress(encoding: &str, data: &[u8]) -> Vec<u8> {
+        match encoding {
+            "gzip" => encode(GzipEncoder::new(data)).await,
+            "deflate" => encode(ZlibEncoder::new(data)).await,
+            "br" => encode(BrotliEncoder::new(data)).await,
+            "zstd" => encode(ZstdEncoder::new(data)).await,
+            _ => unreachable!(),
+        }
+    }
+
+    fn post_encoded(encoding: &str, body: Vec<u8>) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/echo")
+            .header(header::CONTENT_ENCODING, encoding)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn decompresses_every_supported_encoding() {
+        let payload = b"the quick brown fox jumps over the lazy dog".repeat(10);
+
+        for encoding in ["gzip", "deflate", "br", "zstd"] {
+            let compressed = compress(encoding, &payload).await;
+            let res = app(BodyLimits::default())
+                .oneshot(post_encoded(encoding, compressed))
+                .await
+                .unwrap();
+
+            assert_eq!(res.status(), StatusCode::OK, "{encoding}");
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, payload, "{encoding}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_body_before_reading_it() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = Router::new()
+            .route(
+                "/echo",
+                post(move |body: Bytes| async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    body
+                }),
+            )
+            .layer(
+                BodyLimits {
+                    max_body_bytes: 16,
+                    ..Default::default()
+                }
+                .layer(),
+            );
+
+        // the body here is never polled, the Content-Length alone is enough to refuse it
+        let res = router
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/echo")
+                    .header(header::CONTENT_LENGTH, 1024)
+                    .body(Body::from(vec![0u8; 1024]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    // 8MiB of zeroes gzips down to a few KiB, which is exactly what a zip bomb looks like.
+    #[tokio::test]
+    async fn rejects_zip_bombs() {
+        let compressed = compress("gzip", &vec![0u8; 8 * 1024 * 1024]).await;
+        let limits = BodyLimits {
+            max_body_bytes: 64 * 1024,
+            max_decompressed_bytes: 1024 * 1024,
+        };
+        assert!(compressed.len() < limits.max_body_bytes);
+
+        let res = app(limits)
+            .oneshot(post_encoded("gzip", compressed))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_encodings() {
+        let res = app(BodyLimits::default())
+            .oneshot(post_encoded("compress", b"whatever".to_vec()))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,950 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::body::{Body, Bytes, HttpBody};
+use futures::{FutureExt, StreamExt};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri, header,
+};
+use sha2::{Digest, Sha256};
+use tokio::time::{Duration, Instant};
+use tower::{BoxError, Layer, Service};
+
+/// Caches successful `GET` responses in memory, and answers `HEAD` requests from them too.
+///
+/// Entries are keyed by method, uri and the request headers named in the response's `Vary`
+/// header, so placing this outside a `CompressionLayer` stores each encoding separately.
+///
+/// Like any shared cache it doesn't store responses marked `private` or `no-cache`, ones that
+/// set a cookie, nor ones to requests with an `Authorization` header unless the response says
+/// they can be shared.
+/// Only bodies that are already complete and fit in the cache are stored; the rest, like a
+/// stream of events, are passed on as they come.
+#[derive(Clone)]
+pub struct CacheLayer {
+    cache: Arc<Mutex<ResponseCache>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl CacheLayer {
+    /// `max_bytes` bounds the total size of cached bodies, `ttl` is used when the response
+    /// doesn't set its own `max-age`.
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(ResponseCache::new(max_bytes))),
+            max_bytes,
+            ttl,
+        }
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = Cache<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cache {
+            inner,
+            cache: self.cache.clone(),
+            max_bytes: self.max_bytes,
+            ttl: self.ttl,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Cache<S> {
+    inner: S,
+    cache: Arc<Mutex<ResponseCache>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Cache<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let head = req.method() == Method::HEAD;
+        let cacheable = matches!(*req.method(), Method::GET | Method::HEAD);
+        let request_cc = CacheControl::parse(req.headers());
+        let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+        let authorized = req.headers().contains_key(header::AUTHORIZATION);
+
+        if !cacheable || request_cc.no_store {
+            return self.pass_through(req);
+        }
+
+        let uri = req.uri().clone();
+        let request_headers = req.headers().clone();
+
+        // `no-cache` and `max-age=0` from the client mean revalidate, so skip the lookup but
+        // still refresh the entry with whatever comes back.
+        if !request_cc.no_cache && request_cc.max_age != Some(0) {
+            let hit = self
+                .cache
+                .lock()
+                .unwrap()
+                .get(&Method::GET, &uri, &request_headers);
+            if let Some(entry) = hit {
+                let res = entry.respond(if_none_match.as_ref());
+                return Box::pin(async move {
+                    Ok(if head {
+                        res.map(|_| Body::empty())
+                    } else {
+                        res
+                    })
+                });
+            }
+        }
+
+        // a HEAD response has no body to store, and its ETag has to be the GET one's
+        if head {
+            return self.pass_through(req);
+        }
+
+        let cache = self.cache.clone();
+        let max_bytes = self.max_bytes;
+        let default_ttl = self.ttl;
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await.map_err(Into::into)?;
+            if res.status() != StatusCode::OK {
+                return Ok(res.map(Body::new));
+            }
+
+            let response_cc = CacheControl::parse(res.headers());
+            // RFC 9111 §3.5: what one user gets can't go to another unless it says so
+            let shared = !authorized
+                || response_cc.public
+                || response_cc.s_maxage
+                || response_cc.must_revalidate;
+            // someone's session cookie mustn't be replayed to everyone else
+            let sets_cookie = res.headers().contains_key(header::SET_COOKIE);
+            let vary = match vary_headers(res.headers()) {
+                Some(vary)
+                    if shared
+                        && !sets_cookie
+                        && !response_cc.no_store
+                        && !response_cc.no_cache
+                        && !response_cc.private =>
+                {
+                    vary
+                }
+                // `Vary: *` can never match a later request, so don't bother storing those
+                _ => return Ok(res.map(Body::new)),
+            };
+
+            let (mut parts, body) = res.into_parts();
+            let body = match buffer(Body::new(body), max_bytes)? {
+                Buffered::Whole(body) => body,
+                Buffered::Streaming(body) => return Ok(Response::from_parts(parts, body)),
+            };
+            let etag = match parts.headers.get(header::ETAG) {
+                Some(etag) => etag.clone(),
+                None => {
+                    let etag = strong_etag(&body);
+                    parts.headers.insert(header::ETAG, etag.clone());
+                    etag
+                }
+            };
+
+            let entry = Arc::new(CachedResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+                etag,
+                expires_at: Instant::now()
+                    + response_cc
+                        .max_age
+                        .map(Duration::from_secs)
+                        .unwrap_or(default_ttl),
+            });
+            cache
+                .lock()
+                .unwrap()
+                .insert(Method::GET, uri, &request_headers, vary, entry.clone());
+
+            Ok(entry.respond(if_none_match.as_ref()))
+        })
+    }
+}
+
+impl<S> Cache<S> {
+    fn pass_through<ReqBody, ResBody>(
+        &mut self,
+        req: Request<ReqBody>,
+    ) -> Pin<Box<dyn Future<Output = Result<Response<Body>, BoxError>> + Send>>
+    where
+        S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+        ResBody: HttpBody<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await.map_err(Into::into)?;
+            Ok(res.map(Body::new))
+        })
+    }
+}
+
+enum Buffered {
+    Whole(Bytes),
+    Streaming(Body),
+}
+
+// Reads the body if all of it is already there and it's no bigger than `limit`. Otherwise
+// what was read goes back in front of the rest, which is left to stream.
+fn buffer(body: Body, limit: usize) -> Result<Buffered, BoxError> {
+    if body.size_hint().lower() > limit as u64 {
+        return Ok(Buffered::Streaming(body));
+    }
+    let mut stream = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while len <= limit {
+        match stream.next().now_or_never() {
+            Some(Some(chunk)) => {
+                let chunk = chunk?;
+                len += chunk.len();
+                chunks.push(chunk);
+            }
+            Some(None) => return Ok(Buffered::Whole(chunks.concat().into())),
+            // still being produced, so it's not ours to hold up
+            None => break,
+        }
+    }
+    let read = futures::stream::iter(chunks.into_iter().map(Ok));
+    Ok(Buffered::Streaming(Body::from_stream(read.chain(stream))))
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    etag: HeaderValue,
+    expires_at: Instant,
+}
+
+impl CachedResponse {
+    fn respond(&self, if_none_match: Option<&HeaderValue>) -> Response<Body> {
+        if if_none_match.is_some_and(|tags| etag_matches(tags, &self.etag)) {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            // a 304 carries the headers a 200 would have, minus the ones describing the body
+            for name in [
+                header::ETAG,
+                header::CACHE_CONTROL,
+                header::VARY,
+                header::CONTENT_LOCATION,
+                header::EXPIRES,
+            ] {
+                for value in self.headers.get_all(&name) {
+                    res.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            return res;
+        }
+
+        let (mut parts, ()) = Response::new(()).into_parts();
+        parts.status = self.status;
+        parts.headers = self.headers.clone();
+        Response::from_parts(parts, Body::from(self.body.clone()))
+    }
+
+    fn size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>()
+    }
+}
+
+fn strong_etag(body: &[u8]) -> HeaderValue {
+    let digest = Sha256::digest(body);
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&digest[..16])))
+        .expect("hex is a valid header value")
+}
+
+// If-None-Match uses weak comparison, so `W/"x"` matches `"x"`.
+fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(tags) = if_none_match.to_str() else {
+        return false;
+    };
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The request headers a response varies on, or `None` for `Vary: *`.
+fn vary_headers(headers: &HeaderMap) -> Option<Vec<HeaderName>> {
+    let mut names = Vec::new();
+    for value in headers.get_all(header::VARY) {
+        for name in value.to_str().ok()?.split(',').map(str::trim) {
+            if name == "*" {
+                return None;
+            }
+            if let Ok(name) = HeaderName::try_from(name) {
+                names.push(name);
+            }
+        }
+    }
+    names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    names.dedup();
+    Some(names)
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    s_maxage: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut cc = CacheControl::default();
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_ascii_lowercase());
+
+        for directive in directives {
+            match directive.split_once('=') {
+                // we're a shared cache, so s-maxage wins over max-age
+                Some(("s-maxage", secs)) => {
+                    cc.s_maxage = true;
+                    cc.max_age = secs.trim_matches('"').parse().ok();
+                }
+                Some(("max-age", secs)) if cc.max_age.is_none() => {
+                    cc.max_age = secs.trim_matches('"').parse().ok()
+                }
+                Some(("private", _)) => cc.private = true,
+                Some(("no-cache", _)) => cc.no_cache = true,
+                _ => match directive.as_str() {
+                    "no-store" => cc.no_store = true,
+                    "no-cache" => cc.no_cache = true,
+                    "private" => cc.private = true,
+                    "public" => cc.public = true,
+                    "must-revalidate" => cc.must_revalidate = true,
+                    _ => {}
+                },
+            }
+        }
+        cc
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    method: Method,
+    uri: Uri,
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl CacheKey {
+    fn new(method: Method, uri: Uri, names: &[HeaderName], headers: &HeaderMap) -> Self {
+        let vary = names
+            .iter()
+            .map(|name| (name.clone(), headers.get(name).cloned()))
+            .collect();
+        Self { method, uri, vary }
+    }
+}
+
+/// A byte-bounded LRU of responses.
+struct ResponseCache {
+    max_bytes: usize,
+    used_bytes: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (Arc<CachedResponse>, u64)>,
+    // tick -> key, the first entry is the least recently used one
+    recency: BTreeMap<u64, CacheKey>,
+    // the Vary header names last seen for each method + uri, and how many entries use them
+    vary: HashMap<(Method, Uri), (Vec<HeaderName>, usize)>,
+}
+
+impl ResponseCache {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            used_bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            vary: HashMap::new(),
+        }
+    }
+
+    fn get(
+        &mut self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+    ) -> Option<Arc<CachedResponse>> {
+        let (names, _) = self.vary.get(&(method.clone(), uri.clone()))?;
+        let key = CacheKey::new(method.clone(), uri.clone(), names, headers);
+
+        let entry = self.entries.get(&key)?.0.clone();
+        if entry.expires_at <= Instant::now() {
+            self.remove(&key);
+            return None;
+        }
+        self.touch(&key);
+        Some(entry)
+    }
+
+    fn insert(
+        &mut self,
+        method: Method,
+        uri: Uri,
+        headers: &HeaderMap,
+        vary: Vec<HeaderName>,
+        entry: Arc<CachedResponse>,
+    ) {
+        let size = entry.size();
+        if size > self.max_bytes {
+            return;
+        }
+
+        let key = CacheKey::new(method.clone(), uri.clone(), &vary, headers);
+        self.remove(&key);
+        let (names, count) = self.vary.entry((method, uri)).or_default();
+        *names = vary;
+        *count += 1;
+
+        while self.used_bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.used_bytes += size;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (entry, self.tick));
+    }
+
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        if let Some((_, tick)) = self.entries.get_mut(key) {
+            self.recency.remove(tick);
+            *tick = self.tick;
+            self.recency.insert(self.tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((entry, tick)) = self.entries.remove(key) {
+            self.recency.remove(&tick);
+            self.used_bytes -= entry.size();
+
+            let uri_key = (key.method.clone(), key.uri.clone());
+            if let Some((_, count)) = self.vary.get_mut(&uri_key) {
+                *count -= 1;
+                if *count == 0 {
+                    self.vary.remove(&uri_key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::{ServiceBuilder, ServiceExt};
+    use tower_http::compression::CompressionLayer;
+
+    const BODY: &str = "a long enough body that the compression layer will want to gzip it";
+
+    fn counting_app(
+        calls: Arc<AtomicUsize>,
+        cache: CacheLayer,
+    ) -> impl Clone + Service<Request<Body>, Response = Response<Body>, Error = BoxError, Future: Send>
+    {
+        let router = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    ([(header::CONTENT_TYPE, "text/plain")], BODY)
+                }),
+            )
+            .route(
+                "/private",
+                get(|| async { ([(header::CACHE_CONTROL, "private")], "secret") }),
+            )
+            .route(
+                "/login",
+                get(|| async { ([(header::SET_COOKIE, "session=alice")], "welcome") }),
+            );
+
+        ServiceBuilder::new()
+            .layer(cache)
+            .layer(CompressionLayer::new())
+            .service(router)
+    }
+
+    fn get_req(uri: &str) -> http::request::Builder {
+        Request::builder().uri(uri)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn serves_repeat_requests_from_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(
+            calls.clone(),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+
+        for _ in 0..3 {
+            let res = app
+                .clone()
+                .oneshot(get_req("/").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().contains_key(header::ETAG));
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, BODY);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_after_ttl() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(
+            calls.clone(),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+
+        app.clone()
+            .oneshot(get_req("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(61)).await;
+        app.oneshot(get_req("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answers_if_none_match_with_304() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(
+            calls.clone(),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+
+        let res = app
+            .clone()
+            .oneshot(get_req("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let etag = res.headers()[header::ETAG].clone();
+
+        let res = app
+            .clone()
+            .oneshot(
+                get_req("/")
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        // a stale tag gets the full body
+        let res = app
+            .oneshot(
+                get_req("/")
+                    .header(header::IF_NONE_MATCH, "\"something-else\"")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_compressed_variants_separately() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(
+            calls.clone(),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+
+        let gzip = || {
+            get_req("/")
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let plain = || get_req("/").body(Body::empty()).unwrap();
+
+        let gzipped = app.clone().oneshot(gzip()).await.unwrap();
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let identity = app.clone().oneshot(plain()).await.unwrap();
+        assert!(!identity.headers().contains_key(header::CONTENT_ENCODING));
+        assert_ne!(
+            gzipped.headers()[header::ETAG],
+            identity.headers()[header::ETAG]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // both variants are now cached
+        let gzipped = app.clone().oneshot(gzip()).await.unwrap();
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let identity = app.oneshot(plain()).await.unwrap();
+        let body = axum::body::to_bytes(identity.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, BODY);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honours_cache_control() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = CacheLayer::new(1024 * 1024, Duration::from_secs(60));
+        let app = counting_app(calls.clone(), cache.clone());
+
+        app.clone()
+            .oneshot(get_req("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // no-cache from the client forces a trip to the handler
+        app.clone()
+            .oneshot(
+                get_req("/")
+                    .header(header::CACHE_CONTROL, "no-cache")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // private responses are never stored
+        app.clone()
+            .oneshot(get_req("/private").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(
+            cache
+                .cache
+                .lock()
+                .unwrap()
+                .vary
+                .keys()
+                .all(|(_, uri)| uri.path() != "/private")
+        );
+
+        // nor are ones setting a cookie, which would hand one user's session to the next
+        for _ in 0..2 {
+            let res = app
+                .clone()
+                .oneshot(get_req("/login").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.headers()[header::SET_COOKIE], "session=alice");
+        }
+        assert!(
+            cache
+                .cache
+                .lock()
+                .unwrap()
+                .vary
+                .keys()
+                .all(|(_, uri)| uri.path() != "/login")
+        );
+    }
+
+    fn app(
+        router: Router,
+        cache: CacheLayer,
+    ) -> impl Clone + Service<Request<Body>, Response = Response<Body>, Error = BoxError, Future: Send>
+    {
+        ServiceBuilder::new().layer(cache).service(router)
+    }
+
+    async fn body_of(res: Response<Body>) -> Bytes {
+        axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn doesnt_store_no_cache_responses() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let app = app(
+            Router::new().route(
+                "/",
+                get(move || async move {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    ([(header::CACHE_CONTROL, "no-cache")], "check with me first")
+                }),
+            ),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+
+        for _ in 0..2 {
+            app.clone()
+                .oneshot(get_req("/").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shares_authorized_responses_only_when_they_say_so() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let app = app(
+            Router::new()
+                .route(
+                    "/me",
+                    get(|headers: HeaderMap| async move {
+                        headers[header::AUTHORIZATION].to_str().unwrap().to_owned()
+                    }),
+                )
+                .route(
+                    "/charts",
+                    get(move || async move {
+                        counted.fetch_add(1, Ordering::SeqCst);
+                        ([(header::CACHE_CONTROL, "public, max-age=60")], "top 10")
+                    }),
+                ),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+        let as_user = |uri: &str, token: &str| {
+            get_req(uri)
+                .header(header::AUTHORIZATION, token)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for token in ["Bearer alice", "Bearer bob"] {
+            let res = app.clone().oneshot(as_user("/me", token)).await.unwrap();
+            assert_eq!(body_of(res).await, token);
+        }
+
+        for token in ["Bearer alice", "Bearer bob"] {
+            let res = app
+                .clone()
+                .oneshot(as_user("/charts", token))
+                .await
+                .unwrap();
+            assert_eq!(body_of(res).await, "top 10");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answers_head_from_the_get_entry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(
+            calls.clone(),
+            CacheLayer::new(1024 * 1024, Duration::from_secs(60)),
+        );
+        let head = || Request::builder().method(Method::HEAD).uri("/");
+
+        let res = app
+            .clone()
+            .oneshot(get_req("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let etag = res.headers()[header::ETAG].clone();
+
+        let res = app
+            .clone()
+            .oneshot(head().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ETAG], etag);
+        assert!(body_of(res).await.is_empty());
+
+        let res = app
+            .clone()
+            .oneshot(
+                head()
+                    .header(header::IF_NONE_MATCH, etag)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn streams_bodies_it_wont_store() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (big, slow) = (calls.clone(), calls.clone());
+        let app = app(
+            Router::new()
+                .route(
+                    "/big",
+                    get(move || async move {
+                        big.fetch_add(1, Ordering::SeqCst);
+                        BODY
+                    }),
+                )
+                .route(
+                    "/slow",
+                    get(move || async move {
+                        slow.fetch_add(1, Ordering::SeqCst);
+                        let events =
+                            futures::stream::iter(["one", "two"]).then(|event| async move {
+                                tokio::time::sleep(Duration::from_millis(10)).await;
+                                Ok::<_, std::convert::Infallible>(event)
+                            });
+                        Body::from_stream(events)
+                    }),
+                ),
+            CacheLayer::new(BODY.len() / 2, Duration::from_secs(60)),
+        );
+
+        for _ in 0..2 {
+            let res = app
+                .clone()
+                .oneshot(get_req("/big").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(body_of(res).await, BODY);
+            let res = app
+                .clone()
+                .oneshot(get_req("/slow").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(body_of(res).await, "onetwo");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn buffers_only_what_fits_and_is_already_there() {
+        let Ok(Buffered::Whole(body)) = buffer(Body::from("ready"), 5) else {
+            panic!("a complete body that fits is buffered");
+        };
+        assert_eq!(body, "ready");
+        assert!(matches!(
+            buffer(Body::from("ready"), 4),
+            Ok(Buffered::Streaming(_))
+        ));
+
+        let chunks = ["a", "b", "c"].map(Ok::<_, std::convert::Infallible>);
+        let Ok(Buffered::Streaming(body)) =
+            buffer(Body::from_stream(futures::stream::iter(chunks)), 2)
+        else {
+            panic!("a body past the limit streams");
+        };
+        let rest = futures::executor::block_on(axum::body::to_bytes(body, usize::MAX)).unwrap();
+        assert_eq!(rest, "abc");
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let entry = |body: &'static str| {
+            Arc::new(CachedResponse {
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: Bytes::from_static(body.as_bytes()),
+                etag: strong_etag(body.as_bytes()),
+                expires_at: Instant::now() + Duration::from_secs(60),
+            })
+        };
+        let headers = HeaderMap::new();
+        let uri = |s: &str| s.parse::<Uri>().unwrap();
+
+        let mut cache = ResponseCache::new(10);
+        cache.insert(Method::GET, uri("/a"), &headers, vec![], entry("aaaa"));
+        cache.insert(Method::GET, uri("/b"), &headers, vec![], entry("bbbb"));
+        // touch /a so /b becomes the oldest entry
+        assert!(cache.get(&Method::GET, &uri("/a"), &headers).is_some());
+        cache.insert(Method::GET, uri("/c"), &headers, vec![], entry("cccc"));
+
+        assert!(cache.get(&Method::GET, &uri("/a"), &headers).is_some());
+        assert!(cache.get(&Method::GET, &uri("/b"), &headers).is_none());
+        assert!(cache.get(&Method::GET, &uri("/c"), &headers).is_some());
+        assert_eq!(cache.used_bytes, 8);
+        assert_eq!(cache.vary.len(), 2);
+    }
+
+    #[test]
+    fn parses_cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=30, s-maxage=10"),
+        );
+        assert_eq!(
+            CacheControl::parse(&headers),
+            CacheControl {
+                public: true,
+                s_maxage: true,
+                max_age: Some(10),
+                ..Default::default()
+            }
+        );
+
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        assert!(CacheControl::parse(&headers).no_store);
+    }
+}
@@ -6,15 +6,21 @@ mod tests {
     use tower::limit::ConcurrencyLimitLayer;
     use tower_http::{compression::CompressionLayer, trace::TraceLayer};
 
+    use crate::tower::body_limits::BodyLimits;
+    use crate::tower::request_id::{REQUEST_ID_HEADER, RequestIdLayer, RequestIdMakeSpan};
+
     async fn hello() -> &'static str {
         "hello world"
     }
 
+    // the request id layer goes first so the trace span can pick the id up
     pub fn build_app() -> Router {
         Router::new().route("/", get(hello)).layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
+                .layer(RequestIdLayer)
+                .layer(TraceLayer::new_for_http().make_span_with(RequestIdMakeSpan))
                 .layer(CompressionLayer::new())
+                .layer(BodyLimits::default().layer())
                 .layer(ConcurrencyLimitLayer::new(5)),
         )
     }
@@ -34,6 +40,7 @@ mod tests {
             .unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(REQUEST_ID_HEADER));
 
         let body_bytes: Bytes = body::to_bytes(response.into_body(), usize::MAX)
             .await
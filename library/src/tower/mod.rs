@@ -1,2 +1,6 @@
+pub mod auth;
+pub mod body_limits;
+pub mod cache;
 pub mod http;
 pub mod rate_limit;
+pub mod request_id;
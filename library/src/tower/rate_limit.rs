@@ -1,3 +1,92 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::time::{Duration, Instant};
+use tower::Service;
+
+/// Token bucket state for a single client
+struct TokenBucketState {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+/// Multi-client token bucket middleware
+#[derive(Clone)]
+pub struct MultiRateLimiter<S> {
+    inner: S,
+    capacity: u32,
+    refill_interval: Duration,
+    state: Arc<Mutex<HashMap<String, TokenBucketState>>>,
+}
+
+impl<S> MultiRateLimiter<S> {
+    pub fn new(inner: S, capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            inner,
+            capacity,
+            refill_interval,
+            state: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn try_acquire(&self, client_id: &str) -> bool {
+        let mut state_map = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        // Get or insert state for this client
+        let entry = state_map
+            .entry(client_id.to_string())
+            .or_insert(TokenBucketState {
+                tokens: self.capacity,
+                last_refill: now,
+            });
+
+        // refill tokens
+        let elapsed = now.duration_since(entry.last_refill);
+        let new_tokens = (elapsed.as_millis() / self.refill_interval.as_millis()) as u32;
+        if new_tokens > 0 {
+            entry.tokens = (entry.tokens + new_tokens).min(self.capacity);
+            entry.last_refill = now;
+        }
+
+        if entry.tokens > 0 {
+            entry.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<S, Request> Service<(String, Request)> for MultiRateLimiter<S>
+where
+    S: Service<Request> + Send + 'static,
+    S::Future: Send + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = &'static str; // Reject with static str on rate-limit
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(|_| "inner not ready")
+    }
+
+    fn call(&mut self, (client_id, req): (String, Request)) -> Self::Future {
+        if self.try_acquire(&client_id) {
+            let fut = self.inner.call(req);
+            Box::pin(async move { fut.await.map_err(|_| "inner error") })
+        } else {
+            Box::pin(async { Err("rate limited") })
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // tower provides a trait for writing networked services:
@@ -44,89 +133,7 @@ mod tests {
         assert_eq!(resp, 42);
     }
 
-    use std::{
-        collections::HashMap,
-        sync::{Arc, Mutex},
-    };
-
-    use tokio::time::{Duration, Instant};
-
-    /// Token bucket state for a single client
-    struct TokenBucketState {
-        tokens: u32,
-        last_refill: Instant,
-    }
-
-    /// Multi-client token bucket middleware
-    pub struct MultiRateLimiter<S> {
-        inner: S,
-        capacity: u32,
-        refill_interval: Duration,
-        state: Arc<Mutex<HashMap<String, TokenBucketState>>>,
-    }
-
-    impl<S> MultiRateLimiter<S> {
-        pub fn new(inner: S, capacity: u32, refill_interval: Duration) -> Self {
-            Self {
-                inner,
-                capacity,
-                refill_interval,
-                state: Arc::new(Mutex::new(HashMap::new())),
-            }
-        }
-
-        fn try_acquire(&self, client_id: &str) -> bool {
-            let mut state_map = self.state.lock().unwrap();
-            let now = Instant::now();
-
-            // Get or insert state for this client
-            let entry = state_map
-                .entry(client_id.to_string())
-                .or_insert(TokenBucketState {
-                    tokens: self.capacity,
-                    last_refill: now,
-                });
-
-            // refill tokens
-            let elapsed = now.duration_since(entry.last_refill);
-            let new_tokens = (elapsed.as_millis() / self.refill_interval.as_millis()) as u32;
-            if new_tokens > 0 {
-                entry.tokens = (entry.tokens + new_tokens).min(self.capacity);
-                entry.last_refill = now;
-            }
-
-            if entry.tokens > 0 {
-                entry.tokens -= 1;
-                true
-            } else {
-                false
-            }
-        }
-    }
-
-    impl<S, Request> Service<(String, Request)> for MultiRateLimiter<S>
-    where
-        S: Service<Request> + Send + 'static,
-        S::Future: Send + 'static,
-        Request: Send + 'static,
-    {
-        type Response = S::Response;
-        type Error = &'static str; // Reject with static str on rate-limit
-        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
-
-        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-            self.inner.poll_ready(cx).map_err(|_| "inner not ready")
-        }
-
-        fn call(&mut self, (client_id, req): (String, Request)) -> Self::Future {
-            if self.try_acquire(&client_id) {
-                let fut = self.inner.call(req);
-                Box::pin(async move { fut.await.map_err(|_| "inner error") })
-            } else {
-                Box::pin(async { Err("rate limited") })
-            }
-        }
-    }
+    use super::*;
 
     // we use tokio::test with start_paused for testing
     #[tokio::test(start_paused = true)]
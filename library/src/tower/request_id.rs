@@ -0,0 +1,350 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http::{HeaderName, HeaderValue, Request, Response};
+use tower::{Layer, Service};
+use tower_http::trace::MakeSpan;
+use tracing::Span;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// ids from upstream services are reused as long as they look sane, otherwise we mint our own.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// The id of the request currently being handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Generates a new UUIDv7 id, which sorts by creation time.
+    pub fn new() -> Self {
+        let id = Uuid::now_v7().to_string();
+        Self(HeaderValue::from_str(&id).expect("uuids are valid header values"))
+    }
+
+    /// Reuses an incoming `x-request-id` value, if it is printable and not too long.
+    pub fn from_header(value: &HeaderValue) -> Option<Self> {
+        let s = value.to_str().ok()?;
+        if s.is_empty() || s.len() > MAX_REQUEST_ID_LEN {
+            return None;
+        }
+        Some(Self(value.clone()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0
+            .to_str()
+            .expect("request ids are checked to be visible ascii")
+    }
+
+    pub fn header_value(&self) -> &HeaderValue {
+        &self.0
+    }
+
+    /// The id of the request the current task is serving, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    /// Runs `fut` with this id available through [`RequestId::current`].
+    pub fn scope<F: Future>(self, fut: F) -> impl Future<Output = F::Output> {
+        CURRENT_REQUEST_ID.scope(self, fut)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reads or generates `x-request-id`, stores it in the request extensions and echoes it back on
+/// the response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestIdService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_default();
+
+        // overwrite the header too, so handlers reading headers directly see the same id
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER, id.header_value().clone());
+        req.extensions_mut().insert(id.clone());
+
+        let fut = self.inner.call(req);
+        Box::pin(id.clone().scope(async move {
+            let mut res = fut.await?;
+            res.headers_mut()
+                .insert(REQUEST_ID_HEADER, id.header_value().clone());
+            Ok(res)
+        }))
+    }
+}
+
+/// A `MakeSpan` for `TraceLayer` that records the request id on the span.
+/// `RequestIdLayer` has to run before the `TraceLayer` for the id to be there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIdMakeSpan;
+
+impl<B> MakeSpan<B> for RequestIdMakeSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(RequestId::to_string)
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            method = %req.method(),
+            uri = %req.uri(),
+            version = ?req.version(),
+            request_id = %request_id,
+        )
+    }
+}
+
+/// Copies the current request id onto outgoing requests so logs can be joined across services.
+pub trait PropagateRequestId {
+    fn propagate_request_id(self) -> Self;
+}
+
+impl PropagateRequestId for http::request::Builder {
+    fn propagate_request_id(self) -> Self {
+        match RequestId::current() {
+            Some(id) => self.header(REQUEST_ID_HEADER, id.header_value().clone()),
+            None => self,
+        }
+    }
+}
+
+// isahc is still on http 0.2, so its builder is a different type.
+impl PropagateRequestId for isahc::http::request::Builder {
+    fn propagate_request_id(self) -> Self {
+        match RequestId::current() {
+            Some(id) => self.header(REQUEST_ID_HEADER.as_str(), id.as_str()),
+            None => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Extension, Router, body::Body, routing::get};
+    use pretty_assertions::assert_eq;
+    use std::sync::{Arc, Mutex};
+    use tower::{ServiceBuilder, ServiceExt};
+    use tower_http::trace::TraceLayer;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(id): Extension<RequestId>| async move { id.to_string() }),
+            )
+            .layer(
+                ServiceBuilder::new()
+                    .layer(RequestIdLayer)
+                    .layer(TraceLayer::new_for_http().make_span_with(RequestIdMakeSpan)),
+            )
+    }
+
+    async fn body_string(res: Response<axum::body::Body>) -> String {
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn generates_uuid_v7_when_missing() {
+        let res = app()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let header = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let uuid = Uuid::parse_str(&header).unwrap();
+        assert_eq!(uuid.get_version_num(), 7);
+
+        // the handler saw the same id as the one echoed back
+        assert_eq!(body_string(res).await, header);
+    }
+
+    #[tokio::test]
+    async fn reuses_incoming_id() {
+        let res = app()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(REQUEST_ID_HEADER, "upstream-123")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "upstream-123");
+        assert_eq!(body_string(res).await, "upstream-123");
+    }
+
+    #[tokio::test]
+    async fn replaces_oversized_id() {
+        let res = app()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(REQUEST_ID_HEADER, "a".repeat(MAX_REQUEST_ID_LEN + 1))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let header = res.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(Uuid::parse_str(header).is_ok());
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the events TraceLayer emits are printed with the span fields, including the request id.
+    #[tokio::test]
+    async fn records_id_on_trace_span() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        app()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(REQUEST_ID_HEADER, "trace-me")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("request_id=trace-me"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn propagates_to_outgoing_isahc_requests() {
+        let server = httpmock::MockServer::start_async().await;
+        let downstream = server
+            .mock_async(|when, then| {
+                when.path("/downstream")
+                    .header(REQUEST_ID_HEADER.as_str(), "joined-456");
+                then.status(200);
+            })
+            .await;
+
+        let url = server.url("/downstream");
+        let router = Router::new()
+            .route(
+                "/",
+                get(move || {
+                    let url = url.clone();
+                    async move {
+                        let req = isahc::Request::get(url)
+                            .propagate_request_id()
+                            .body(())
+                            .unwrap();
+                        isahc::send_async(req).await.unwrap().status().to_string()
+                    }
+                }),
+            )
+            .layer(RequestIdLayer);
+
+        let res = router
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(REQUEST_ID_HEADER, "joined-456")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(body_string(res).await, "200 OK");
+        downstream.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn no_current_id_outside_a_request() {
+        assert_eq!(RequestId::current(), None);
+
+        let req = http::Request::builder()
+            .propagate_request_id()
+            .body(())
+            .unwrap();
+        assert!(req.headers().get(REQUEST_ID_HEADER).is_none());
+    }
+}
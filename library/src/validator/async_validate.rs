@@ -0,0 +1,312 @@
+use axum::{
+    Json,
+    extract::{FromRef, FromRequest, Request},
+};
+use futures::future::{BoxFuture, join_all};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use super::{
+    extract::{SerdeNames, ValidationRejection},
+    schema::Schema,
+};
+
+pub use rust_learning_derive::AsyncValidate;
+
+/// One async rule running against one field.
+pub type AsyncCheck<'a> = BoxFuture<'a, Result<(), ValidationError>>;
+
+/// The outcome of running every async rule on a value.
+pub type AsyncValidation<'a> = BoxFuture<'a, Result<(), ValidationErrors>>;
+
+/// Validation that needs to wait on something, like a database, passed in as the `Context`.
+///
+/// Usually derived, see [`rust_learning_derive::AsyncValidate`].
+pub trait AsyncValidate {
+    type Context: Sync;
+
+    /// Runs only the async rules.
+    fn validate_async<'a>(&'a self, ctx: &'a Self::Context) -> AsyncValidation<'a>;
+
+    /// Runs the `Validate` rules and the async rules, and merges their errors.
+    fn validate_all<'a>(&'a self, ctx: &'a Self::Context) -> AsyncValidation<'a>
+    where
+        Self: Validate + Sync,
+    {
+        Box::pin(async move {
+            match (self.validate(), self.validate_async(ctx).await) {
+                (Ok(()), Ok(())) => Ok(()),
+                (Err(errors), Ok(())) | (Ok(()), Err(errors)) => Err(errors),
+                (Err(mut errors), Err(more)) => {
+                    for (field, kind) in more.into_errors() {
+                        merge_field(&mut errors, field, kind);
+                    }
+                    Err(errors)
+                }
+            }
+        })
+    }
+}
+
+fn merge_field(
+    errors: &mut ValidationErrors,
+    field: std::borrow::Cow<'static, str>,
+    kind: ValidationErrorsKind,
+) {
+    match (errors.errors_mut().get_mut(&field), kind) {
+        (Some(ValidationErrorsKind::Field(existing)), ValidationErrorsKind::Field(more)) => {
+            existing.extend(more)
+        }
+        (_, kind) => {
+            errors.errors_mut().insert(field, kind);
+        }
+    }
+}
+
+/// Runs every check at once and collects the failures by field.
+pub async fn run_checks(
+    checks: Vec<(&'static str, AsyncCheck<'_>)>,
+) -> Result<(), ValidationErrors> {
+    let (fields, futures): (Vec<_>, Vec<_>) = checks.into_iter().unzip();
+    let mut errors = ValidationErrors::new();
+    for (field, result) in fields.into_iter().zip(join_all(futures).await) {
+        if let Err(error) = result {
+            errors.add(field, error);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Like [`ValidatedJson`](super::extract::ValidatedJson), but also runs the async rules, with
+/// the context pulled out of the router state.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for AsyncValidatedJson<T>
+where
+    T: Validate + AsyncValidate + DeserializeOwned + Send + Sync,
+    T::Context: FromRef<S> + Send,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(ValidationRejection::Json)?;
+        let ctx = T::Context::from_ref(state);
+        value
+            .validate_all(&ctx)
+            .await
+            .map_err(ValidationRejection::invalid)?;
+        Ok(Self(value))
+    }
+}
+
+impl<T, S> FromRequest<S> for SerdeNames<AsyncValidatedJson<T>>
+where
+    T: Validate + AsyncValidate + Schema + DeserializeOwned + Send + Sync,
+    T::Context: FromRef<S> + Send,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        AsyncValidatedJson::from_request(req, state)
+            .await
+            .map(Self)
+            .map_err(ValidationRejection::with_serde_names::<T>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::extract::field_errors;
+    use axum::{
+        Router,
+        body::Body,
+        http::{StatusCode, header},
+        routing::post,
+    };
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+    use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[derive(Debug, Validate, AsyncValidate, Deserialize, Schema)]
+    #[async_validate(context = SqlitePool, crate = crate)]
+    #[schema(crate = crate)]
+    struct Signup {
+        #[validate(length(min = 1, max = 30))]
+        #[async_validate(function = unique_username)]
+        username: String,
+        #[validate(email)]
+        #[async_validate(function = "unique_email")]
+        mail: String,
+    }
+
+    async fn taken(pool: &SqlitePool, column: &str, value: &str) -> bool {
+        sqlx::query_scalar::<_, bool>(&format!(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE {column} = ?)"
+        ))
+        .bind(value)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn unique_username(username: &str, pool: &SqlitePool) -> Result<(), ValidationError> {
+        if taken(pool, "username", username).await {
+            Err(ValidationError::new("unique").with_message("username is taken".into()))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn unique_email(mail: &str, pool: &SqlitePool) -> Result<(), ValidationError> {
+        if taken(pool, "mail", mail).await {
+            Err(ValidationError::new("unique"))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE users (username TEXT, mail TEXT)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users VALUES ('xXxShad0wxXx', 'shadow@example.com')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    fn codes(errors: &ValidationErrors) -> Vec<(String, String)> {
+        field_errors::<Signup>(errors)
+            .into_iter()
+            .flat_map(|(field, errors)| errors.into_iter().map(move |e| (field.clone(), e.code)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn checks_against_the_database() {
+        let pool = pool().await;
+
+        let fresh = Signup {
+            username: "someone".into(),
+            mail: "someone@example.com".into(),
+        };
+        assert_eq!(fresh.validate_all(&pool).await, Ok(()));
+
+        let taken = Signup {
+            username: "xXxShad0wxXx".into(),
+            mail: "shadow@example.com".into(),
+        };
+        let errors = taken.validate_async(&pool).await.unwrap_err();
+        assert_eq!(
+            codes(&errors),
+            [
+                ("mail".to_string(), "unique".to_string()),
+                ("username".to_string(), "unique".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merges_sync_and_async_errors() {
+        let pool = pool().await;
+        let signup = Signup {
+            username: "xXxShad0wxXx".into(),
+            mail: "not an email".into(),
+        };
+
+        let errors = signup.validate_all(&pool).await.unwrap_err();
+        assert_eq!(
+            codes(&errors),
+            [
+                ("mail".to_string(), "email".to_string()),
+                ("username".to_string(), "unique".to_string()),
+            ]
+        );
+    }
+
+    #[derive(AsyncValidate)]
+    #[async_validate(context = Duration, crate = crate)]
+    struct Slow {
+        #[async_validate(function = sleep_then_fail)]
+        a: u32,
+        #[async_validate(function = sleep_then_fail)]
+        b: u32,
+    }
+
+    async fn sleep_then_fail(_: &u32, delay: &Duration) -> Result<(), ValidationError> {
+        tokio::time::sleep(*delay).await;
+        Err(ValidationError::new("slow"))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_checks_concurrently() {
+        let start = tokio::time::Instant::now();
+        let errors = Slow { a: 1, b: 2 }
+            .validate_async(&Duration::from_secs(1))
+            .await
+            .unwrap_err();
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert_eq!(errors.errors().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn extracts_with_state() {
+        let app = Router::new()
+            .route(
+                "/signup",
+                post(|AsyncValidatedJson(s): AsyncValidatedJson<Signup>| async move { s.username }),
+            )
+            .with_state(pool().await);
+
+        let signup = |body: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/signup")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(signup(r#"{"username": "new", "mail": "new@example.com"}"#))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app
+            .oneshot(signup(
+                r#"{"username": "xXxShad0wxXx", "mail": "new@example.com"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["errors"]["username"][0]["message"],
+            "username is taken"
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    Form, Json,
+    extract::{
+        FromRequest, FromRequestParts, Query, Request,
+        rejection::{FormRejection, JsonRejection, QueryRejection},
+    },
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+use super::schema::{FieldName, Schema};
+
+/// A `Json<T>` that has also passed `T::validate`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+/// A `Query<T>` that has also passed `T::validate`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+/// A `Form<T>` that has also passed `T::validate`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedForm<T>(pub T);
+
+/// Wraps a validated extractor, as in `SerdeNames<ValidatedJson<Profile>>`, so its 422 names
+/// fields the way serde does for types that rename them. Needs the type's [`Schema`], usually
+/// derived.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeNames<E>(pub E);
+
+/// Why a validated extractor refused a request.
+///
+/// Deserialization failures respond the same way axum's own extractors do. Validation
+/// failures are a 422 whose body maps field paths to what went wrong. The paths use the Rust
+/// field names, or the serde ones under [`SerdeNames`]:
+///
+/// ```json
+/// {"errors": {"address.city": [{"code": "length", "message": null, "params": {"min": 1, "value": ""}}]}}
+/// ```
+#[derive(Debug)]
+pub enum ValidationRejection {
+    Json(JsonRejection),
+    Query(QueryRejection),
+    Form(FormRejection),
+    Invalid {
+        errors: ValidationErrors,
+        /// The wire names of the validated type's fields, if they're known, see
+        /// [`Schema::field_name`].
+        names: fn(&str) -> Option<FieldName>,
+    },
+}
+
+impl ValidationRejection {
+    /// Failed validation, reported by the Rust field names.
+    pub fn invalid(errors: ValidationErrors) -> Self {
+        Self::Invalid {
+            errors,
+            names: unknown_fields,
+        }
+    }
+
+    /// Reports a failed validation of a `T` by its fields' serde names instead.
+    pub fn with_serde_names<T: Schema>(self) -> Self {
+        match self {
+            Self::Invalid { errors, .. } => Self::Invalid {
+                errors,
+                names: T::field_name,
+            },
+            rejection => rejection,
+        }
+    }
+}
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: Validate + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(ValidationRejection::Json)?;
+        value.validate().map_err(ValidationRejection::invalid)?;
+        Ok(Self(value))
+    }
+}
+
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: Validate + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(ValidationRejection::Query)?;
+        value.validate().map_err(ValidationRejection::invalid)?;
+        Ok(Self(value))
+    }
+}
+
+impl<T, S> FromRequest<S> for ValidatedForm<T>
+where
+    T: Validate + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Form(value) = Form::<T>::from_request(req, state)
+            .await
+            .map_err(ValidationRejection::Form)?;
+        value.validate().map_err(ValidationRejection::invalid)?;
+        Ok(Self(value))
+    }
+}
+
+impl<T, S> FromRequest<S> for SerdeNames<ValidatedJson<T>>
+where
+    T: Validate + Schema + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        ValidatedJson::from_request(req, state)
+            .await
+            .map(Self)
+            .map_err(ValidationRejection::with_serde_names::<T>)
+    }
+}
+
+impl<T, S> FromRequestParts<S> for SerdeNames<ValidatedQuery<T>>
+where
+    T: Validate + Schema + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        ValidatedQuery::from_request_parts(parts, state)
+            .await
+            .map(Self)
+            .map_err(ValidationRejection::with_serde_names::<T>)
+    }
+}
+
+impl<T, S> FromRequest<S> for SerdeNames<ValidatedForm<T>>
+where
+    T: Validate + Schema + DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        ValidatedForm::from_request(req, state)
+            .await
+            .map(Self)
+            .map_err(ValidationRejection::with_serde_names::<T>)
+    }
+}
+
+impl IntoResponse for ValidationRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Json(rejection) => rejection.into_response(),
+            Self::Query(rejection) => rejection.into_response(),
+            Self::Form(rejection) => rejection.into_response(),
+            Self::Invalid { errors, names } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorBody {
+                    errors: flatten_errors(&errors, names),
+                }),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The body of a 422.
+#[derive(Serialize, Schema)]
+#[schema(crate = crate)]
+#[serde(rename = "ValidationErrors")]
+pub(crate) struct ErrorBody {
+    errors: BTreeMap<String, Vec<FieldError>>,
+}
+
+/// One failed rule on one field.
+#[derive(Debug, Clone, PartialEq, Serialize, Schema)]
+#[schema(crate = crate)]
+pub struct FieldError {
+    pub code: String,
+    pub message: Option<String>,
+    pub params: BTreeMap<String, Value>,
+}
+
+/// Flattens nested `ValidationErrors` of a `T` into a map keyed by paths like `address.city`
+/// or `tracks[2].title`, using the names fields have once serde renames them.
+pub fn field_errors<T: Schema>(errors: &ValidationErrors) -> BTreeMap<String, Vec<FieldError>> {
+    flatten_errors(errors, T::field_name)
+}
+
+fn flatten_errors(
+    errors: &ValidationErrors,
+    names: fn(&str) -> Option<FieldName>,
+) -> BTreeMap<String, Vec<FieldError>> {
+    let mut out = BTreeMap::new();
+    flatten(errors, names, "", &mut out);
+    out
+}
+
+fn unknown_fields(_: &str) -> Option<FieldName> {
+    None
+}
+
+fn flatten(
+    errors: &ValidationErrors,
+    names: fn(&str) -> Option<FieldName>,
+    prefix: &str,
+    out: &mut BTreeMap<String, Vec<FieldError>>,
+) {
+    for (field, kind) in errors.errors() {
+        // fields the schema doesn't know keep their Rust name
+        let (name, fields) = match names(field) {
+            Some(FieldName { name, fields }) => (name, fields),
+            None => (
+                field.as_ref(),
+                unknown_fields as fn(&str) -> Option<FieldName>,
+            ),
+        };
+        let path = match (prefix, name) {
+            (prefix, "") => prefix.to_owned(),
+            ("", name) => name.to_owned(),
+            (prefix, name) => format!("{prefix}.{name}"),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.entry(path).or_default().extend(errors.iter().map(|e| {
+                    FieldError {
+                        code: e.code.to_string(),
+                        message: e.message.as_ref().map(|m| m.to_string()),
+                        params: e
+                            .params
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.clone()))
+                            .collect(),
+                    }
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => flatten(errors, fields, &path, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    flatten(errors, fields, &format!("{path}[{index}]"), out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        body::Body,
+        http::header,
+        routing::{get, post},
+    };
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct Address {
+        #[validate(length(min = 1, message = "city is required"))]
+        city: String,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct Track {
+        #[validate(length(min = 1))]
+        title: String,
+    }
+
+    // no Schema, the plain extractors don't need one
+    #[derive(Debug, Validate, Deserialize)]
+    struct Signup {
+        #[validate(email)]
+        mail: String,
+        #[validate(range(min = 18, max = 20))]
+        age: u32,
+        #[validate(nested)]
+        address: Address,
+        #[validate(nested)]
+        tracks: Vec<Track>,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct Page {
+        #[validate(range(min = 1, max = 100))]
+        per_page: u32,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[serde(rename_all = "camelCase")]
+    struct Profile {
+        #[validate(length(min = 1))]
+        display_name: String,
+        #[validate(nested)]
+        home_address: Address,
+        #[serde(rename = "favourites")]
+        #[validate(nested)]
+        favourite_tracks: Vec<Track>,
+        #[serde(flatten)]
+        #[validate(nested)]
+        paging: Page,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/profile",
+                post(
+                    |SerdeNames(ValidatedJson(p)): SerdeNames<ValidatedJson<Profile>>| async move {
+                        p.display_name
+                    },
+                ),
+            )
+            .route(
+                "/profile/rust-names",
+                post(|ValidatedJson(p): ValidatedJson<Profile>| async move { p.display_name }),
+            )
+            .route(
+                "/json",
+                post(|ValidatedJson(s): ValidatedJson<Signup>| async move { s.mail }),
+            )
+            .route(
+                "/form",
+                post(|ValidatedForm(p): ValidatedForm<Page>| async move { p.per_page.to_string() }),
+            )
+            .route(
+                "/query",
+                get(
+                    |ValidatedQuery(p): ValidatedQuery<Page>| async move { p.per_page.to_string() },
+                ),
+            )
+    }
+
+    async fn send(req: Request<Body>) -> (StatusCode, Value) {
+        let res = app().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&body)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into())),
+        )
+    }
+
+    fn post_json(body: Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_json() {
+        let (status, body) = send(post_json(json!({
+            "mail": "test@gmail.com",
+            "age": 19,
+            "address": {"city": "Tokyo"},
+            "tracks": [{"title": "one"}],
+        })))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "test@gmail.com");
+    }
+
+    #[tokio::test]
+    async fn reports_nested_field_paths() {
+        let (status, body) = send(post_json(json!({
+            "mail": "not an email",
+            "age": 30,
+            "address": {"city": ""},
+            "tracks": [{"title": "one"}, {"title": ""}],
+        })))
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body,
+            json!({
+                "errors": {
+                    "address.city": [{
+                        "code": "length",
+                        "message": "city is required",
+                        "params": {"min": 1, "value": ""},
+                    }],
+                    "age": [{
+                        "code": "range",
+                        "message": null,
+                        "params": {"min": 18, "max": 20, "value": 30},
+                    }],
+                    "mail": [{
+                        "code": "email",
+                        "message": null,
+                        "params": {"value": "not an email"},
+                    }],
+                    "tracks[1].title": [{
+                        "code": "length",
+                        "message": null,
+                        "params": {"min": 1, "value": ""},
+                    }],
+                }
+            })
+        );
+    }
+
+    async fn invalid_profile_fields(uri: &str) -> Vec<String> {
+        let mut req = post_json(json!({
+            "displayName": "",
+            "homeAddress": {"city": ""},
+            "favourites": [{"title": ""}],
+            "per_page": 0,
+        }));
+        *req.uri_mut() = uri.parse().unwrap();
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        // keys of a json object come out sorted
+        body["errors"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reports_fields_by_their_serde_names() {
+        assert_eq!(
+            invalid_profile_fields("/profile").await,
+            [
+                "displayName",
+                "favourites[0].title",
+                "homeAddress.city",
+                "per_page"
+            ]
+        );
+        assert_eq!(
+            invalid_profile_fields("/profile/rust-names").await,
+            [
+                "display_name",
+                "favourite_tracks[0].title",
+                "home_address.city",
+                "paging.per_page"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_not_a_validation_error() {
+        let (status, _) = send(
+            Request::builder()
+                .method("POST")
+                .uri("/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{not json"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send(
+            Request::builder()
+                .method("POST")
+                .uri("/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn validates_query_and_form() {
+        let (status, body) = send(
+            Request::builder()
+                .uri("/query?per_page=500")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"]["per_page"][0]["code"], "range");
+
+        let (status, body) = send(
+            Request::builder()
+                .method("POST")
+                .uri("/form")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("per_page=20"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, 20);
+
+        let (status, body) = send(
+            Request::builder()
+                .method("POST")
+                .uri("/form")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("per_page=0"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"]["per_page"][0]["params"]["value"], 0);
+    }
+}
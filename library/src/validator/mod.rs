@@ -1,3 +1,8 @@
+pub mod async_validate;
+pub mod extract;
+pub mod openapi;
+pub mod schema;
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    Json, Router,
+    http::{Method, StatusCode},
+    routing::get,
+};
+use serde_json::json;
+
+use super::{
+    async_validate::AsyncValidatedJson,
+    extract::{ErrorBody, SerdeNames, ValidatedForm, ValidatedJson, ValidatedQuery},
+    schema::{Definitions, Map, Schema, Value},
+};
+
+/// How an extractor shows up in an operation: as a request body, parameters, and the
+/// responses it can produce on its own.
+pub trait OperationInput {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions);
+}
+
+fn request_body<T: Schema>(
+    content_type: &str,
+    operation: &mut Map<String, Value>,
+    defs: &mut Definitions,
+) {
+    operation.insert(
+        "requestBody".to_owned(),
+        json!({
+            "required": true,
+            "content": { content_type: { "schema": T::reference(defs) } },
+        }),
+    );
+    validation_failed(operation, defs);
+}
+
+fn validation_failed(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+    let responses = operation
+        .entry("responses")
+        .or_insert_with(|| Value::Object(Map::new()));
+    responses["422"] = json!({
+        "description": "Validation failed",
+        "content": { "application/json": { "schema": ErrorBody::reference(defs) } },
+    });
+}
+
+impl<E: OperationInput> OperationInput for SerdeNames<E> {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+        E::describe(operation, defs);
+    }
+}
+
+impl<T: Schema> OperationInput for ValidatedJson<T> {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+        request_body::<T>("application/json", operation, defs);
+    }
+}
+
+impl<T: Schema> OperationInput for AsyncValidatedJson<T> {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+        request_body::<T>("application/json", operation, defs);
+    }
+}
+
+impl<T: Schema> OperationInput for ValidatedForm<T> {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+        request_body::<T>("application/x-www-form-urlencoded", operation, defs);
+    }
+}
+
+impl<T: Schema> OperationInput for ValidatedQuery<T> {
+    fn describe(operation: &mut Map<String, Value>, defs: &mut Definitions) {
+        // each field is its own parameter, so this needs the schema inline rather than a $ref
+        let schema = T::schema(defs);
+        let required = schema["required"].as_array().cloned().unwrap_or_default();
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, property) in properties {
+                push_parameter(
+                    operation,
+                    json!({
+                        "name": name,
+                        "in": "query",
+                        "required": required.contains(&Value::String(name.clone())),
+                        "schema": property,
+                    }),
+                );
+            }
+        }
+        validation_failed(operation, defs);
+    }
+}
+
+fn push_parameter(operation: &mut Map<String, Value>, parameter: Value) {
+    if let Value::Array(parameters) = operation
+        .entry("parameters")
+        .or_insert_with(|| Value::Array(Vec::new()))
+    {
+        parameters.push(parameter);
+    }
+}
+
+type Describe = Box<dyn FnOnce(&mut Map<String, Value>, &mut Definitions) + Send>;
+
+/// One method on one path.
+#[derive(Default)]
+pub struct Operation {
+    fields: Map<String, Value>,
+    describe: Vec<Describe>,
+}
+
+impl Operation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn summary(mut self, summary: &str) -> Self {
+        self.fields.insert("summary".to_owned(), summary.into());
+        self
+    }
+
+    pub fn operation_id(mut self, id: &str) -> Self {
+        self.fields.insert("operationId".to_owned(), id.into());
+        self
+    }
+
+    /// Documents an extractor the handler takes, e.g. `ValidatedJson<Signup>`.
+    pub fn input<E: OperationInput + 'static>(mut self) -> Self {
+        self.describe.push(Box::new(E::describe));
+        self
+    }
+
+    /// A response with a JSON body.
+    pub fn response<T: Schema + 'static>(mut self, status: StatusCode, description: &str) -> Self {
+        let description = description.to_owned();
+        self.describe.push(Box::new(move |operation, defs| {
+            let response = json!({
+                "description": description,
+                "content": { "application/json": { "schema": T::reference(defs) } },
+            });
+            insert_response(operation, status, response);
+        }));
+        self
+    }
+
+    /// A response without a body.
+    pub fn status(mut self, status: StatusCode, description: &str) -> Self {
+        let description = description.to_owned();
+        self.describe.push(Box::new(move |operation, _| {
+            insert_response(operation, status, json!({ "description": description }));
+        }));
+        self
+    }
+
+    fn build(self, path: &str, defs: &mut Definitions) -> Value {
+        let mut operation = self.fields;
+        for describe in self.describe {
+            describe(&mut operation, defs);
+        }
+
+        // axum and OpenAPI both write path parameters as `{name}`
+        for segment in path.split('/') {
+            if let Some(name) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                push_parameter(
+                    &mut operation,
+                    json!({
+                        "name": name.trim_start_matches('*'),
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    }),
+                );
+            }
+        }
+
+        operation
+            .entry("responses")
+            .or_insert_with(|| json!({ "default": { "description": "" } }));
+        Value::Object(operation)
+    }
+}
+
+fn insert_response(operation: &mut Map<String, Value>, status: StatusCode, response: Value) {
+    let responses = operation
+        .entry("responses")
+        .or_insert_with(|| Value::Object(Map::new()));
+    responses[status.as_str()] = response;
+}
+
+/// Builds an OpenAPI 3.1 document for a set of routes.
+pub struct OpenApi {
+    info: Value,
+    paths: BTreeMap<String, Map<String, Value>>,
+    defs: Definitions,
+}
+
+impl OpenApi {
+    pub fn new(title: &str, version: &str) -> Self {
+        Self {
+            info: json!({ "title": title, "version": version }),
+            paths: BTreeMap::new(),
+            defs: Definitions::openapi(),
+        }
+    }
+
+    pub fn route(mut self, method: Method, path: &str, operation: Operation) -> Self {
+        let operation = operation.build(path, &mut self.defs);
+        self.paths
+            .entry(path.to_owned())
+            .or_default()
+            .insert(method.as_str().to_lowercase(), operation);
+        self
+    }
+
+    pub fn document(&self) -> Value {
+        json!({
+            "openapi": "3.1.0",
+            "info": self.info,
+            "paths": self.paths,
+            "components": { "schemas": self.defs.schemas() },
+        })
+    }
+
+    /// A router serving the document at `GET /openapi.json`.
+    pub fn router<S: Clone + Send + Sync + 'static>(&self) -> Router<S> {
+        let document = self.document();
+        Router::new().route("/openapi.json", get(move || async move { Json(document) }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::schema::Schema;
+    use axum::{body::Body, extract::Request, routing::post};
+    use pretty_assertions::assert_eq;
+    use serde::{Deserialize, Serialize};
+    use tower::ServiceExt;
+    use validator::Validate;
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct Signup {
+        #[validate(email)]
+        mail: String,
+        #[validate(range(min = 18))]
+        age: u32,
+    }
+
+    #[derive(Debug, Serialize, Schema)]
+    #[schema(crate = crate)]
+    struct User {
+        id: i64,
+        mail: String,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[allow(dead_code)]
+    struct Page {
+        #[validate(range(min = 1, max = 100))]
+        per_page: u32,
+        cursor: Option<String>,
+    }
+
+    fn api() -> OpenApi {
+        OpenApi::new("songs", "1.0.0")
+            .route(
+                Method::POST,
+                "/users",
+                Operation::new()
+                    .summary("Sign up")
+                    .input::<ValidatedJson<Signup>>()
+                    .response::<User>(StatusCode::CREATED, "Signed up"),
+            )
+            .route(
+                Method::GET,
+                "/users",
+                Operation::new()
+                    .input::<ValidatedQuery<Page>>()
+                    .response::<Vec<User>>(StatusCode::OK, "A page of users"),
+            )
+            .route(
+                Method::DELETE,
+                "/users/{id}",
+                Operation::new().status(StatusCode::NO_CONTENT, "Deleted"),
+            )
+    }
+
+    #[test]
+    fn documents_request_bodies() {
+        let doc = api().document();
+        assert_eq!(doc["openapi"], "3.1.0");
+
+        let signup = &doc["paths"]["/users"]["post"];
+        assert_eq!(signup["summary"], "Sign up");
+        assert_eq!(
+            signup["requestBody"]["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/Signup" })
+        );
+        assert_eq!(
+            signup["responses"]["201"]["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/User" })
+        );
+        assert_eq!(
+            signup["responses"]["422"]["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/ValidationErrors" })
+        );
+
+        let schemas = &doc["components"]["schemas"];
+        assert_eq!(
+            schemas["Signup"]["properties"]["mail"],
+            json!({ "type": "string", "format": "email" })
+        );
+        assert_eq!(
+            schemas["ValidationErrors"]["properties"]["errors"]["additionalProperties"]["items"],
+            json!({ "$ref": "#/components/schemas/FieldError" })
+        );
+    }
+
+    #[test]
+    fn documents_parameters() {
+        let doc = api().document();
+
+        assert_eq!(
+            doc["paths"]["/users"]["get"]["parameters"],
+            json!([
+                {
+                    "name": "cursor",
+                    "in": "query",
+                    "required": false,
+                    "schema": { "type": ["string", "null"] },
+                },
+                {
+                    "name": "per_page",
+                    "in": "query",
+                    "required": true,
+                    "schema": { "type": "integer", "minimum": 1, "maximum": 100 },
+                },
+            ])
+        );
+        assert_eq!(
+            doc["paths"]["/users/{id}"]["delete"],
+            json!({
+                "parameters": [
+                    { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } },
+                ],
+                "responses": { "204": { "description": "Deleted" } },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_the_document() {
+        let api = api();
+        let app = Router::new()
+            .route(
+                "/users",
+                post(|ValidatedJson(s): ValidatedJson<Signup>| async move { s.mail }),
+            )
+            .merge(api.router());
+
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .uri("/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let doc: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(doc, api.document());
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::Serialize;
+use serde_json::json;
+
+pub use rust_learning_derive::Schema;
+pub use serde_json::{Map, Value};
+
+/// Where named schemas end up, and how `$ref`s point at them.
+#[derive(Debug, Clone)]
+pub struct Definitions {
+    prefix: &'static str,
+    schemas: BTreeMap<String, Value>,
+    // the Rust type behind each name, to catch two types that want the same one
+    types: BTreeMap<String, &'static str>,
+}
+
+impl Definitions {
+    /// For standalone JSON Schema documents, under `$defs`.
+    pub fn json_schema() -> Self {
+        Self {
+            prefix: "#/$defs/",
+            schemas: BTreeMap::new(),
+            types: BTreeMap::new(),
+        }
+    }
+
+    /// For OpenAPI documents, under `components/schemas`.
+    pub fn openapi() -> Self {
+        Self {
+            prefix: "#/components/schemas/",
+            schemas: BTreeMap::new(),
+            types: BTreeMap::new(),
+        }
+    }
+
+    pub fn schemas(&self) -> &BTreeMap<String, Value> {
+        &self.schemas
+    }
+}
+
+/// A type that can describe itself as JSON Schema (draft 2020-12, which OpenAPI 3.1 uses).
+///
+/// Derive it on validated structs, see [`rust_learning_derive::Schema`].
+///
+/// ```
+/// use rust_learning::validator::schema::{Schema, json_schema};
+///
+/// #[derive(Schema)]
+/// struct Song {
+///     title: String,
+///     plays: Option<u64>,
+/// }
+///
+/// assert_eq!(json_schema::<Song>()["required"], serde_json::json!(["title"]));
+/// ```
+pub trait Schema {
+    /// Named types get a definition of their own and are referenced with `$ref`.
+    fn name() -> Option<String> {
+        None
+    }
+
+    /// The schema itself, registering anything it refers to in `defs`.
+    fn schema(defs: &mut Definitions) -> Value;
+
+    /// What the Rust field `field` is called in the schema, for errors like validator's that
+    /// only know the Rust name. `None` for fields it doesn't have.
+    fn field_name(_field: &str) -> Option<FieldName> {
+        None
+    }
+
+    /// What other schemas should embed to refer to this type.
+    ///
+    /// Panics if another type already took the same name, e.g. two `Error` structs from
+    /// different modules. Give one of them a `#[serde(rename)]`.
+    fn reference(defs: &mut Definitions) -> Value {
+        let Some(name) = Self::name() else {
+            return Self::schema(defs);
+        };
+        let ty = std::any::type_name::<Self>();
+        match defs.types.get(&name) {
+            Some(&other) if other != ty => {
+                panic!("{ty} and {other} are both called {name:?} in the schema")
+            }
+            Some(_) => {}
+            None => {
+                // reserve the name first so recursive types don't recurse forever
+                defs.types.insert(name.clone(), ty);
+                defs.schemas.insert(name.clone(), Value::Null);
+                let schema = Self::schema(defs);
+                defs.schemas.insert(name.clone(), schema);
+            }
+        }
+        json!({ "$ref": format!("{}{name}", defs.prefix) })
+    }
+}
+
+/// The name a field goes by in a [`Schema`].
+#[derive(Debug, Clone, Copy)]
+pub struct FieldName {
+    /// Empty for a `#[serde(flatten)]` field, whose fields sit in its parent.
+    pub name: &'static str,
+    /// Names the fields inside this one, for nested structs.
+    pub fields: fn(&str) -> Option<FieldName>,
+}
+
+/// A standalone JSON Schema document for `T`.
+pub fn json_schema<T: Schema>() -> Value {
+    let mut defs = Definitions::json_schema();
+    let mut schema = T::schema(&mut defs);
+    set(
+        &mut schema,
+        "$schema",
+        "https://json-schema.org/draft/2020-12/schema",
+    );
+    if !defs.schemas.is_empty() {
+        set(&mut schema, "$defs", &defs.schemas);
+    }
+    schema
+}
+
+// the helpers below are what #[derive(Schema)] expands to
+
+pub fn set<T: Serialize>(schema: &mut Value, key: &str, value: T) {
+    if let Value::Object(map) = schema {
+        map.insert(key.to_owned(), serde_json::to_value(value).unwrap());
+    }
+}
+
+/// `length` means characters for strings and items for arrays.
+pub fn length<T: Serialize>(schema: &mut Value, bound: &str, value: T) {
+    let is_array = match &schema["type"] {
+        Value::String(ty) => ty == "array",
+        Value::Array(types) => types.iter().any(|ty| ty == "array"),
+        _ => false,
+    };
+    let key = match (bound, is_array) {
+        ("min", false) => "minLength",
+        ("max", false) => "maxLength",
+        ("min", true) => "minItems",
+        _ => "maxItems",
+    };
+    set(schema, key, value);
+}
+
+/// Custom validators can't be expressed as JSON Schema, but consumers should know they exist.
+pub fn custom(schema: &mut Value, function: &str) {
+    if let Value::Object(map) = schema {
+        let validators = map
+            .entry("x-validators")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(validators) = validators {
+            validators.push(function.into());
+        }
+    }
+}
+
+pub fn object(title: &str, properties: Map<String, Value>, required: &[&str]) -> Value {
+    let mut schema = json!({
+        "title": title,
+        "type": "object",
+        "properties": properties,
+    });
+    if !required.is_empty() {
+        set(&mut schema, "required", required);
+    }
+    schema
+}
+
+/// Inlines the properties of a `#[serde(flatten)]` field into its parent's schema, required
+/// unless the field is `optional`.
+pub fn flatten(schema: &mut Value, flattened: Value, optional: bool) {
+    let (Value::Object(schema), Value::Object(mut flattened)) = (schema, flattened) else {
+        return;
+    };
+    if let (Some(Value::Object(properties)), Some(Value::Object(more))) =
+        (schema.get_mut("properties"), flattened.remove("properties"))
+    {
+        properties.extend(more);
+    }
+    if let Some(Value::Array(more)) = flattened.remove("required")
+        && !optional
+    {
+        let required = schema
+            .entry("required")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(required) = required {
+            required.extend(more);
+        }
+    }
+    // a flattened map takes whatever fields are left over
+    if let Some(additional) = flattened.remove("additionalProperties") {
+        schema.insert("additionalProperties".to_owned(), additional);
+    }
+}
+
+macro_rules! primitive_schema {
+    ($schema:tt => $($ty:ty),*) => {
+        $(
+            impl Schema for $ty {
+                fn schema(_: &mut Definitions) -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+primitive_schema!({ "type": "string" } => String, str, char);
+primitive_schema!({ "type": "boolean" } => bool);
+primitive_schema!({ "type": "integer" } => i8, i16, i32, i64, isize);
+primitive_schema!({ "type": "integer", "minimum": 0 } => u8, u16, u32, u64, usize);
+primitive_schema!({ "type": "number" } => f32, f64);
+primitive_schema!({} => Value);
+
+impl<T: Schema + ?Sized> Schema for Box<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        T::reference(defs)
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        let mut schema = T::reference(defs);
+        match schema.get("type").cloned() {
+            Some(Value::String(ty)) => {
+                set(&mut schema, "type", [ty.as_str(), "null"]);
+                schema
+            }
+            _ => json!({ "anyOf": [schema, { "type": "null" }] }),
+        }
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "array", "items": T::reference(defs) })
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema> Schema for BTreeSet<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "array", "items": T::reference(defs), "uniqueItems": true })
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema, S> Schema for HashSet<T, S> {
+    fn schema(defs: &mut Definitions) -> Value {
+        BTreeSet::<T>::schema(defs)
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema> Schema for BTreeMap<String, T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "object", "additionalProperties": T::reference(defs) })
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+impl<T: Schema, S> Schema for HashMap<String, T, S> {
+    fn schema(defs: &mut Definitions) -> Value {
+        BTreeMap::<String, T>::schema(defs)
+    }
+
+    fn field_name(field: &str) -> Option<FieldName> {
+        T::field_name(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+    use validator::{Validate, ValidationError};
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct SignupData {
+        #[validate(email)]
+        mail: String,
+        #[validate(url)]
+        site: String,
+        #[validate(
+            length(min = 1, max = 30),
+            custom(function = "validate_unique_username")
+        )]
+        #[serde(rename = "firstName")]
+        first_name: String,
+        #[validate(range(min = 18, max = 20))]
+        age: u32,
+        #[validate(range(exclusive_min = 0.0, max = 100.0))]
+        height: f32,
+    }
+
+    fn validate_unique_username(username: &str) -> Result<(), ValidationError> {
+        if username.starts_with("xxx") && username.ends_with("xxx") {
+            Err(ValidationError::new("terrible_username"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn signup_schema() {
+        assert_eq!(
+            json_schema::<SignupData>(),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "SignupData",
+                "type": "object",
+                "properties": {
+                    "mail": { "type": "string", "format": "email" },
+                    "site": { "type": "string", "format": "uri" },
+                    "firstName": {
+                        "type": "string",
+                        "minLength": 1,
+                        "maxLength": 30,
+                        "x-validators": ["validate_unique_username"],
+                    },
+                    "age": { "type": "integer", "minimum": 18, "maximum": 20 },
+                    "height": { "type": "number", "exclusiveMinimum": 0.0, "maximum": 100.0 },
+                },
+                "required": ["mail", "site", "firstName", "age", "height"],
+            })
+        );
+    }
+
+    /// A song on an album.
+    #[derive(Debug, Validate, Serialize, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[serde(rename_all = "camelCase")]
+    struct Track {
+        /// Shown in the player.
+        #[validate(length(min = 1))]
+        track_title: String,
+        #[serde(default)]
+        play_count: u64,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    struct Album {
+        #[validate(length(min = 1, max = 20), nested)]
+        tracks: Vec<Track>,
+        #[validate(length(max = 500))]
+        notes: Option<String>,
+        #[serde(skip)]
+        #[allow(dead_code)]
+        cached: bool,
+    }
+
+    #[test]
+    fn nested_and_optional_fields() {
+        assert_eq!(
+            json_schema::<Album>(),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "Album",
+                "type": "object",
+                "properties": {
+                    "tracks": {
+                        "type": "array",
+                        "items": { "$ref": "#/$defs/Track" },
+                        "minItems": 1,
+                        "maxItems": 20,
+                    },
+                    "notes": { "type": ["string", "null"], "maxLength": 500 },
+                },
+                "required": ["tracks"],
+                "$defs": {
+                    "Track": {
+                        "title": "Track",
+                        "description": "A song on an album.",
+                        "type": "object",
+                        "properties": {
+                            "trackTitle": {
+                                "type": "string",
+                                "description": "Shown in the player.",
+                                "minLength": 1,
+                            },
+                            "playCount": { "type": "integer", "minimum": 0 },
+                        },
+                        "required": ["trackTitle"],
+                    },
+                },
+            })
+        );
+    }
+
+    #[derive(Debug, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[allow(dead_code)]
+    struct Paging {
+        #[serde(rename(serialize = "pageSize", deserialize = "per_page"))]
+        page_size: u32,
+        cursor: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[allow(dead_code)]
+    struct Search {
+        query: String,
+        #[serde(flatten)]
+        paging: Paging,
+        #[serde(flatten)]
+        filters: Option<Filters>,
+        #[serde(flatten)]
+        rest: BTreeMap<String, String>,
+    }
+
+    #[derive(Debug, Deserialize, Schema)]
+    #[schema(crate = crate)]
+    #[allow(dead_code)]
+    struct Filters {
+        artist: String,
+    }
+
+    #[test]
+    fn flattened_fields_sit_next_to_their_parents() {
+        assert_eq!(
+            json_schema::<Search>(),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "Search",
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "per_page": { "type": "integer", "minimum": 0 },
+                    "cursor": { "type": ["string", "null"] },
+                    "artist": { "type": "string" },
+                },
+                "required": ["query", "per_page"],
+                "additionalProperties": { "type": "string" },
+            })
+        );
+    }
+
+    mod tracks {
+        #[derive(super::Schema)]
+        #[schema(crate = crate)]
+        #[allow(dead_code)]
+        pub struct Error {
+            pub track: u32,
+        }
+    }
+
+    mod albums {
+        #[derive(super::Schema)]
+        #[schema(crate = crate)]
+        #[allow(dead_code)]
+        pub struct Error {
+            pub album: u32,
+        }
+    }
+
+    #[derive(Schema)]
+    #[schema(crate = crate)]
+    #[allow(dead_code)]
+    struct Errors {
+        track: tracks::Error,
+        album: albums::Error,
+    }
+
+    #[test]
+    #[should_panic(expected = "are both called \"Error\" in the schema")]
+    fn two_types_with_the_same_name_panic() {
+        json_schema::<Errors>();
+    }
+}
@@ -0,0 +1,70 @@
+use std::{alloc::System, collections::TryReserveError};
+
+use quickcheck_macros::quickcheck;
+use rust_learning::{
+    allocator::{FailingAlloc, Faults},
+    errors::{CustomError, CustomErrorKind},
+};
+
+#[global_allocator]
+static ALLOC: FailingAlloc<System> = FailingAlloc::new(System);
+
+// Copies the words into one buffer without an infallible allocation anywhere, so running out
+// of memory comes back as an error.
+fn concat(words: &[String]) -> Result<String, TryReserveError> {
+    let mut joined = String::new();
+    joined.try_reserve(words.iter().map(String::len).sum())?;
+    for word in words {
+        joined.push_str(word);
+    }
+    Ok(joined)
+}
+
+fn copy_all(chunks: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, TryReserveError> {
+    let mut copies = Vec::new();
+    copies.try_reserve_exact(chunks.len())?;
+    for chunk in chunks {
+        let mut copy = Vec::new();
+        copy.try_reserve_exact(chunk.len())?;
+        copy.extend_from_slice(chunk);
+        copies.push(copy);
+    }
+    Ok(copies)
+}
+
+#[quickcheck]
+fn concat_errors_over_budget(words: Vec<String>, budget: u16) -> bool {
+    let needed: usize = words.iter().map(String::len).sum();
+    let budget = budget as usize;
+    let (joined, failed) = ALLOC.inject(Faults::new().byte_budget(budget), || concat(&words));
+
+    // outside the scope, since making a `CustomError` allocates
+    match joined.map_err(CustomError::from) {
+        Ok(joined) => needed <= budget && failed == 0 && joined == words.concat(),
+        Err(e) => needed > budget && failed == 1 && e.kind() == CustomErrorKind::OutOfMemory,
+    }
+}
+
+#[quickcheck]
+fn copies_fail_cleanly_after_any_count(chunks: Vec<Vec<u8>>, after: u8) -> bool {
+    let allocations =
+        chunks.iter().filter(|chunk| !chunk.is_empty()).count() + usize::from(!chunks.is_empty());
+    let (copies, failed) =
+        ALLOC.inject(Faults::new().fail_after(after as u64), || copy_all(&chunks));
+
+    match copies.map_err(CustomError::from) {
+        Ok(copies) => copies == chunks && allocations <= after as usize && failed == 0,
+        Err(e) => {
+            e.kind() == CustomErrorKind::OutOfMemory && allocations > after as usize && failed == 1
+        }
+    }
+}
+
+#[quickcheck]
+fn random_failures_never_abort(chunks: Vec<Vec<u8>>, seed: u64) -> bool {
+    let (copies, _) = ALLOC.inject(Faults::new().probability(0.1, seed), || copy_all(&chunks));
+    match copies.map_err(CustomError::from) {
+        Ok(copies) => copies == chunks,
+        Err(e) => e.kind() == CustomErrorKind::OutOfMemory,
+    }
+}
@@ -0,0 +1,66 @@
+use std::{alloc::System, hint::black_box};
+
+use ctor::ctor;
+use rust_learning::allocator::{HeapProfiler, TrackingAlloc};
+
+static PROFILER: HeapProfiler = HeapProfiler::new();
+
+#[global_allocator]
+static ALLOC: TrackingAlloc<System> = TrackingAlloc::new(System).with_profiler(&PROFILER);
+
+#[ctor]
+fn sample_everything() {
+    PROFILER.set_sample_rate(1.0);
+}
+
+#[inline(never)]
+fn load_playlist(songs: usize) -> Vec<String> {
+    (0..songs).map(|i| format!("song number {i:04}")).collect()
+}
+
+#[test]
+fn finds_the_site_holding_memory() {
+    let playlist = black_box(load_playlist(1000));
+
+    let report = PROFILER.report();
+    let site = report
+        .live()
+        .find(|site| {
+            site.frames
+                .iter()
+                .any(|frame| frame.ends_with("load_playlist"))
+        })
+        .expect("no site under load_playlist");
+    assert!(site.live_bytes >= 16 * 1000, "{site:?}");
+    assert!(report.to_text().contains("load_playlist"));
+    assert!(
+        report
+            .to_collapsed()
+            .lines()
+            .any(|line| line.contains("heap_profile::load_playlist"))
+    );
+
+    drop(playlist);
+    let report = PROFILER.report();
+    assert!(!report.live().any(|site| {
+        site.frames
+            .iter()
+            .any(|frame| frame.ends_with("load_playlist"))
+    }));
+}
+
+#[test]
+fn dumps_text_and_collapsed_stacks() {
+    let dir = tempfile::tempdir().unwrap();
+    let _kept = black_box(load_playlist(10));
+
+    PROFILER.dump(dir.path().join("heap")).unwrap();
+
+    let text = std::fs::read_to_string(dir.path().join("heap.txt")).unwrap();
+    let folded = std::fs::read_to_string(dir.path().join("heap.folded")).unwrap();
+    assert!(text.contains("sampling 100% of allocations"), "{text}");
+    assert!(folded.lines().all(|line| {
+        line.rsplit_once(' ')
+            .is_some_and(|(_, bytes)| bytes.parse::<u64>().is_ok())
+    }));
+}
@@ -0,0 +1,67 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rust_learning::allocator::{SecretBox, SecureAlloc};
+
+// Counts blocks that came back to it with anything but zeros in them. Unlike the spy in
+// `alloc.rs` it sees every path back to the system allocator, realloc included.
+struct Spy {
+    dirty: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Spy {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+        if bytes.iter().any(|&b| b != 0) {
+            self.dirty.fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe { System.dealloc(ptr, layout) };
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if unsafe { std::slice::from_raw_parts(ptr, layout.size()) }
+            .iter()
+            .any(|&b| b != 0)
+        {
+            self.dirty.fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: SecureAlloc<Spy> = SecureAlloc::new(Spy {
+    dirty: AtomicUsize::new(0),
+})
+.with_guard_pages(64 * 1024);
+
+#[test]
+fn nothing_goes_back_dirty() {
+    let mut token = String::with_capacity(1);
+    for _ in 0..1000 {
+        token.push_str(black_box("secret"));
+    }
+    let mut big = black_box(vec![0xAB_u8; 100 * 1024]);
+    big.resize(300 * 1024, 0xCD);
+    drop((token, big));
+
+    assert_eq!(ALLOC.inner().dirty.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn secret_box_round_trips() {
+    let key = SecretBox::init_with(|key: &mut [u8; 7]| key.copy_from_slice(b"hunter2"));
+    assert_eq!(key.expose(), b"hunter2");
+    assert_eq!(format!("{key:?}"), "SecretBox(<redacted>)");
+}
@@ -0,0 +1,73 @@
+// A binary of its own, because the SIGTERM goes to the whole process and would stop any
+// other test's server along with this one.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{Router, routing::get};
+use rust_learning::axum::serve::{
+    ServeConfig, ShutdownReport, serve_with_shutdown, shutdown_signal,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+    time::{sleep, timeout},
+};
+
+#[tokio::test]
+async fn drains_on_sigterm() {
+    let handling = Arc::new(Notify::new());
+    let started = handling.clone();
+    let app = Router::new().route(
+        "/slow",
+        get(move || async move {
+            started.notify_one();
+            sleep(Duration::from_millis(200)).await;
+            "finished"
+        }),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_with_shutdown(
+        listener,
+        app,
+        ServeConfig {
+            readiness_grace: Duration::ZERO,
+            ..Default::default()
+        },
+        shutdown_signal(),
+    ));
+
+    let request = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = String::new();
+        let _ = stream.read_to_string(&mut res).await;
+        res
+    });
+
+    // the server polls the signal before it accepts anything, so once a request is being
+    // handled the SIGTERM handler is in place
+    handling.notified().await;
+    assert_eq!(unsafe { libc::kill(libc::getpid(), libc::SIGTERM) }, 0);
+
+    let report = timeout(Duration::from_secs(5), server)
+        .await
+        .expect("server did not stop on SIGTERM")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        report,
+        ShutdownReport {
+            drained: 1,
+            aborted: 0
+        }
+    );
+    let res = request.await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+    assert!(res.ends_with("finished"), "{res}");
+}
@@ -0,0 +1,98 @@
+use std::{alloc::System, collections::HashMap, hint::black_box};
+
+use rust_learning::allocator::{Arena, TrackingAlloc};
+
+#[global_allocator]
+static ALLOC: TrackingAlloc<System> = TrackingAlloc::new(System);
+
+fn join_words(words: &[&str]) -> String {
+    let mut joined = String::with_capacity(words.iter().map(|w| w.len() + 1).sum());
+    for word in words {
+        joined.push_str(word);
+        joined.push(' ');
+    }
+    joined
+}
+
+#[test]
+fn presized_string_allocates_once() {
+    let words = ["kenshi", "yonezu", "lemon"];
+    let (joined, stats) = ALLOC.measure(|| join_words(&words));
+
+    assert_eq!(joined, "kenshi yonezu lemon ");
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.reallocations, 0);
+    assert_eq!(stats.live_bytes, 20);
+}
+
+#[test]
+fn growing_a_vec_reallocates() {
+    let (_, stats) = ALLOC.measure(|| {
+        let mut v = Vec::new();
+        for i in 0..100_u64 {
+            v.push(black_box(i));
+        }
+        v
+    });
+
+    assert_eq!(stats.allocations, 1);
+    assert!(stats.reallocations >= 3, "{stats:?}");
+    assert!(stats.peak_bytes >= 800, "{stats:?}");
+}
+
+#[test]
+fn borrowing_does_not_allocate() {
+    let map = HashMap::from([("lemon", 1), ("idol", 2)]);
+    let (found, stats) = ALLOC.measure(|| map.get("lemon").copied());
+
+    assert_eq!(found, Some(1));
+    assert_eq!(stats.allocations + stats.reallocations, 0);
+}
+
+#[test]
+fn ignores_other_threads() {
+    let busy = std::thread::spawn(|| {
+        for _ in 0..10_000 {
+            black_box(vec![0_u8; 128]);
+        }
+    });
+    let (_, stats) = ALLOC.measure(|| {
+        for i in 0..10_000 {
+            black_box(i);
+        }
+    });
+    busy.join().unwrap();
+
+    assert_eq!(stats.allocations, 0);
+}
+
+#[test]
+fn global_totals_add_up() {
+    let before = ALLOC.stats();
+    drop(black_box(vec![0_u8; 4096]));
+    let after = ALLOC.stats();
+
+    assert!(after.allocations > before.allocations);
+    assert!(after.bytes_allocated - before.bytes_allocated >= 4096);
+    assert!(after.peak_bytes >= after.live_bytes);
+}
+
+#[test]
+fn arena_allocates_chunks_not_values() {
+    let mut arena = Arena::new();
+    let (_, first) = ALLOC.measure(|| {
+        for i in 0..1000_u64 {
+            arena.alloc(black_box(i));
+        }
+    });
+    arena.reset();
+    let (_, again) = ALLOC.measure(|| {
+        for i in 0..1000_u64 {
+            arena.alloc(black_box(i));
+        }
+    });
+
+    assert!(first.allocations <= 3, "{first:?}");
+    assert_eq!(again.allocations, 0);
+    assert_eq!(arena.stats().bytes_allocated, 2 * 8000);
+}